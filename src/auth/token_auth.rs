@@ -1,10 +1,54 @@
-use actix_web::dev::{Service, ServiceRequest, ServiceResponse, Transform};
-use actix_web::{Error, HttpResponse};
+use actix_web::dev::{Payload, Service, ServiceRequest, ServiceResponse, Transform};
+use actix_web::{Error, FromRequest, HttpMessage, HttpRequest, HttpResponse};
 use base64::Engine;
 use base64::engine::general_purpose::STANDARD as BASE64_STANDARD;
 use futures::future::{LocalBoxFuture, Ready, ready};
 use log::{info, warn};
 use secrecy::{ExposeSecret, Secret};
+
+/// 通过认证的调用者，Basic 认证时为其中的用户名，Bearer 令牌没有用户名
+///
+/// 不需要认证的请求中 `name` 为空。
+#[derive(Debug, Clone, Default)]
+pub struct AuthUser {
+    pub name: Option<String>,
+}
+
+impl AuthUser {
+    /// 以调用者身份生成签名，没有用户名时使用服务端身份
+    pub fn signature(&self) -> Result<git2::Signature<'static>, git2::Error> {
+        match &self.name {
+            Some(name) => git2::Signature::now(name, &format!("{}@localhost", name)),
+            None => git2::Signature::now("git-demo", "git-demo@localhost"),
+        }
+    }
+}
+
+impl FromRequest for AuthUser {
+    type Error = Error;
+    type Future = Ready<Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+        ready(Ok(req
+            .extensions()
+            .get::<AuthUser>()
+            .cloned()
+            .unwrap_or_default()))
+    }
+}
+
+// /repos/{name}/ 下读取仓库内容的接口
+const REPO_CONTENT_ROUTES: &[&str] = &["raw", "tree", "commits", "compare", "blame", "archive"];
+
+fn is_repo_content(path: &str) -> bool {
+    let mut segments = path.split('/').skip(1);
+    segments.next() == Some("repos")
+        && segments.next().is_some()
+        && segments
+            .next()
+            .is_some_and(|route| REPO_CONTENT_ROUTES.contains(&route))
+}
+
 pub struct TokenAuthMiddleware;
 
 impl<S> Transform<S, ServiceRequest> for TokenAuthMiddleware
@@ -50,7 +94,16 @@ where
         // 只保护 Git 操作端点
         let requires_auth = path.ends_with(".git")
             || path.contains("/info/refs")
-            || path.contains("/git-upload-pack");
+            || path.contains("/git-upload-pack")
+            || path.contains("/git-receive-pack")
+            // 仓库管理接口中会修改数据的请求
+            || (path.starts_with("/repos/") && req.method() != actix_web::http::Method::GET)
+            // 仓库内容的读取与克隆一样需要认证
+            || is_repo_content(path)
+            // 上游凭据的查看和修改
+            || path.starts_with("/credentials")
+            // 后台任务的查看和取消，任务结果中包含远程地址和仓库内容
+            || path.starts_with("/jobs");
         // || path.contains("/HEAD");
         info!("requires_auth的结果是{:?}", requires_auth);
         // 获取有效 Token
@@ -72,13 +125,13 @@ where
                     info!("Authorization header: {}", auth_str);
 
                     // Bearer 认证
-                    if auth_str.starts_with("Bearer ") {
+                    if let Some(token) = auth_str.strip_prefix("Bearer ") {
                         info!("进入Bearer 认证");
-                        let token = &auth_str[7..];
                         info!("Bearer token: {}", token);
 
                         if token == valid_token.expose_secret() {
                             info!("Bearer token validation successful");
+                            req.extensions_mut().insert(AuthUser::default());
                             let fut = self.service.call(req);
                             return Box::pin(async move {
                                 let res = fut.await?;
@@ -89,33 +142,37 @@ where
                         }
                     }
                     // Basic 认证
-                    else if auth_str.starts_with("Basic ") {
+                    else if let Some(encoded) = auth_str.strip_prefix("Basic ") {
                         info!("进入Basic 认证");
                         info!("Basic auth detected");
 
-                        if let Ok(decoded) = BASE64_STANDARD.decode(&auth_str[6..]) {
-                            if let Ok(creds) = String::from_utf8(decoded) {
-                                info!("Decoded credentials: {}", creds);
-
-                                // 格式为 "username:password"
-                                let parts: Vec<&str> = creds.splitn(2, ':').collect();
-                                if parts.len() == 2 {
-                                    let password = parts[1];
-                                    info!("Password extracted: {}", password);
-
-                                    if password == valid_token.expose_secret() {
-                                        info!("Basic auth validation successful");
-                                        let fut = self.service.call(req);
-                                        return Box::pin(async move {
-                                            let res = fut.await?;
-                                            Ok(res)
-                                        });
-                                    } else {
-                                        warn!("Basic auth password mismatch");
-                                    }
+                        if let Ok(decoded) = BASE64_STANDARD.decode(encoded)
+                            && let Ok(creds) = String::from_utf8(decoded)
+                        {
+                            info!("Decoded credentials: {}", creds);
+
+                            // 格式为 "username:password"
+                            let parts: Vec<&str> = creds.splitn(2, ':').collect();
+                            if parts.len() == 2 {
+                                let (username, password) = (parts[0], parts[1]);
+                                info!("Password extracted: {}", password);
+
+                                if password == valid_token.expose_secret() {
+                                    info!("Basic auth validation successful");
+                                    req.extensions_mut().insert(AuthUser {
+                                        name: (!username.is_empty())
+                                            .then(|| username.to_string()),
+                                    });
+                                    let fut = self.service.call(req);
+                                    return Box::pin(async move {
+                                        let res = fut.await?;
+                                        Ok(res)
+                                    });
                                 } else {
-                                    warn!("Invalid Basic auth format");
+                                    warn!("Basic auth password mismatch");
                                 }
+                            } else {
+                                warn!("Invalid Basic auth format");
                             }
                         }
                     }
@@ -141,3 +198,29 @@ where
         })
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn repo_content_routes_are_detected() {
+        for path in [
+            "/repos/demo/raw/main/README.md",
+            "/repos/demo/tree/main/",
+            "/repos/demo/commits",
+            "/repos/demo/commits/abc123",
+            "/repos/team%2Fdemo/archive/main.tar.gz",
+        ] {
+            assert!(is_repo_content(path), "{}", path);
+        }
+        for path in [
+            "/repos/demo/settings",
+            "/repos/demo/branches",
+            "/repos/raw",
+            "/other/demo/raw/main/a",
+        ] {
+            assert!(!is_repo_content(path), "{}", path);
+        }
+    }
+}
@@ -0,0 +1,241 @@
+use config::{Config, File, FileFormat};
+use log::warn;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::sync::OnceLock;
+
+static CONFIG_PATH: &str = "src/config/config.toml";
+
+/// 服务端配置，对应 `src/config/config.toml`
+#[derive(Debug, Default, Deserialize)]
+pub struct AppConfig {
+    // 新建仓库时写入的 git 配置，按 `[section] key = value` 组织
+    #[serde(default)]
+    pub repo_config: GitConfigTable,
+    #[serde(default)]
+    pub quota_config: QuotaConfig,
+    #[serde(default)]
+    pub template_config: TemplateConfig,
+    #[serde(default)]
+    pub mirror_config: MirrorSchedulerConfig,
+    #[serde(default)]
+    pub webhook_config: WebhookConfig,
+    #[serde(default)]
+    pub credential_config: CredentialConfig,
+    #[serde(default)]
+    pub ssh_config: SshConfig,
+    #[serde(default)]
+    pub push_mirror_config: PushMirrorRetryConfig,
+    #[serde(default)]
+    pub job_config: JobConfig,
+}
+
+/// `[repo_config]` 这类 git 配置表，TOML 中的 `user.name = ""` 会解析为 `user -> name`
+pub type GitConfigTable = HashMap<String, HashMap<String, toml::Value>>;
+
+/// 将配置表展开为 `section.key = value` 形式，跳过空字符串
+pub fn flatten_git_config(table: &GitConfigTable) -> Vec<(String, String)> {
+    let mut entries = Vec::new();
+    for (section, values) in table {
+        for (key, value) in values {
+            let value = match value {
+                toml::Value::String(s) if s.is_empty() => continue,
+                toml::Value::String(s) => s.clone(),
+                other => other.to_string(),
+            };
+            entries.push((format!("{}.{}", section, key), value));
+        }
+    }
+    entries.sort();
+    entries
+}
+
+/// 磁盘配额配置，单位 MB，0 表示不限制
+#[derive(Debug, Default, Deserialize)]
+pub struct QuotaConfig {
+    // 每个仓库的默认上限
+    #[serde(default)]
+    pub repo_limit_mb: u64,
+    // 每个命名空间的默认上限
+    #[serde(default)]
+    pub namespace_limit_mb: u64,
+    // 按仓库名单独设置的上限
+    #[serde(default)]
+    pub repos: HashMap<String, u64>,
+    // 按命名空间单独设置的上限
+    #[serde(default)]
+    pub namespaces: HashMap<String, u64>,
+}
+
+/// 仓库模板配置
+#[derive(Debug, Deserialize)]
+pub struct TemplateConfig {
+    // 模板根目录，每个子目录是一个模板
+    #[serde(default = "default_template_dir")]
+    pub dir: String,
+}
+
+impl Default for TemplateConfig {
+    fn default() -> Self {
+        Self {
+            dir: default_template_dir(),
+        }
+    }
+}
+
+fn default_template_dir() -> String {
+    "templates".to_string()
+}
+
+/// 镜像后台定时同步配置，时间单位均为秒
+#[derive(Debug, Deserialize)]
+#[serde(default)]
+pub struct MirrorSchedulerConfig {
+    // 是否启动后台调度
+    pub enabled: bool,
+    // 调度器检查到期任务的周期
+    pub tick_secs: u64,
+    // 重新扫描 bare_repos 中镜像登记的周期，登记接口的修改会立即生效
+    pub rescan_secs: u64,
+    // 登记时未指定间隔的默认同步间隔
+    pub default_interval_secs: u64,
+    // 每次排期附加的随机抖动上限
+    pub jitter_secs: u64,
+    // 失败后第一次重试的等待时间，之后按指数翻倍
+    pub retry_base_secs: u64,
+    // 失败重试等待时间的上限
+    pub max_backoff_secs: u64,
+}
+
+impl Default for MirrorSchedulerConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            tick_secs: 5,
+            rescan_secs: 300,
+            default_interval_secs: 600,
+            jitter_secs: 30,
+            retry_base_secs: 30,
+            max_backoff_secs: 3600,
+        }
+    }
+}
+
+/// 入站 webhook 配置
+#[derive(Debug, Default, Deserialize)]
+pub struct WebhookConfig {
+    // 各平台的签名密钥（GitLab 为 Secret token），键为 github / gitlab / gitea
+    // 环境变量 WEBHOOK_SECRET_<PROVIDER> 优先，未配置的平台拒绝所有请求
+    #[serde(default)]
+    pub secrets: HashMap<String, String>,
+}
+
+/// 上游凭据配置
+#[derive(Debug, Deserialize)]
+pub struct CredentialConfig {
+    // 加密凭据文件的路径，主密钥由环境变量 CREDENTIAL_KEY 提供
+    #[serde(default = "default_credential_path")]
+    pub path: String,
+    // 可以收到环境变量 GIT_TOKEN 的主机（可带端口），仓库上游所在的主机总是可以
+    #[serde(default)]
+    pub git_token_hosts: Vec<String>,
+}
+
+impl Default for CredentialConfig {
+    fn default() -> Self {
+        Self {
+            path: default_credential_path(),
+            git_token_hosts: Vec::new(),
+        }
+    }
+}
+
+fn default_credential_path() -> String {
+    "credentials.enc".to_string()
+}
+
+/// SSH 上游的主机密钥校验策略
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum HostKeyPolicy {
+    // 只接受 known_hosts 中已有且一致的主机密钥
+    Strict,
+    // 首次连接时把主机密钥写入 known_hosts，之后与 strict 相同
+    AcceptNew,
+    // 不校验主机密钥，仅用于测试环境
+    AcceptAny,
+}
+
+/// SSH 上游配置
+#[derive(Debug, Deserialize)]
+#[serde(default)]
+pub struct SshConfig {
+    // known_hosts 文件路径，支持 ~/ 开头
+    pub known_hosts: String,
+    pub host_key_policy: HostKeyPolicy,
+    // 没有登记凭据时是否尝试 SSH_AUTH_SOCK 指向的 ssh-agent
+    pub use_agent: bool,
+}
+
+impl Default for SshConfig {
+    fn default() -> Self {
+        Self {
+            known_hosts: "~/.ssh/known_hosts".to_string(),
+            host_key_policy: HostKeyPolicy::AcceptNew,
+            use_agent: true,
+        }
+    }
+}
+
+/// 下游推送镜像的重试配置
+#[derive(Debug, Deserialize)]
+#[serde(default)]
+pub struct PushMirrorRetryConfig {
+    // 每轮推送的最多尝试次数
+    pub max_attempts: u32,
+    // 失败后第一次重试的等待时间（秒），之后按指数翻倍
+    pub retry_base_secs: u64,
+}
+
+impl Default for PushMirrorRetryConfig {
+    fn default() -> Self {
+        Self {
+            max_attempts: 3,
+            retry_base_secs: 10,
+        }
+    }
+}
+
+/// 克隆、拉取等后台任务的配置
+#[derive(Debug, Deserialize)]
+#[serde(default)]
+pub struct JobConfig {
+    // 同时执行的任务数，其余任务排队
+    pub max_concurrent: usize,
+    // 结束的任务保留多久（秒）以便查询结果
+    pub retention_secs: u64,
+}
+
+impl Default for JobConfig {
+    fn default() -> Self {
+        Self {
+            max_concurrent: 2,
+            retention_secs: 3600,
+        }
+    }
+}
+
+/// 获取全局配置，首次调用时从文件加载，文件缺失或格式错误时使用默认值
+pub fn app_config() -> &'static AppConfig {
+    static CONFIG: OnceLock<AppConfig> = OnceLock::new();
+    CONFIG.get_or_init(|| {
+        Config::builder()
+            .add_source(File::new(CONFIG_PATH, FileFormat::Toml).required(false))
+            .build()
+            .and_then(|c| c.try_deserialize())
+            .unwrap_or_else(|e| {
+                warn!("⚠️ 加载配置文件 {} 失败，使用默认配置: {}", CONFIG_PATH, e);
+                AppConfig::default()
+            })
+    })
+}
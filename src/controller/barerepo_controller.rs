@@ -1,13 +1,25 @@
 use crate::repo::barerepo_manager::RepoManager;
+use crate::repo::pkt_line::{
+    err_line, parse_receive_commands, pkt_line, read_command_section, reject_receive_pack,
+};
+use crate::repo::repo_path::RepoPath;
+use crate::service::{mirror_service, push_mirror_service, quota_service, settings_service};
 use actix_web::Error;
 use actix_web::web::Data;
 use actix_web::{HttpResponse, Responder, get, post, web};
+use futures::StreamExt;
+use git2::{Indexer, Oid, Repository};
+use log::warn;
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{Seek, SeekFrom, Write};
 use std::sync::Arc;
+use tempfile::TempDir;
 
 #[get("/{repo_name}/info/refs")]
 async fn info_refs(
     repo_name: web::Path<String>,
-    // query: web::Query<HashMap<String, String>>,
+    query: web::Query<HashMap<String, String>>,
     repo_manager: Data<Arc<RepoManager>>,
 ) -> Result<HttpResponse, Error> {
     let full_path = repo_name.into_inner();
@@ -18,6 +30,25 @@ async fn info_refs(
         return Ok(HttpResponse::NotFound().body("Repository not found"));
     }
 
+    if query.get("service").map(String::as_str) == Some("git-receive-pack") {
+        let repo_path = RepoPath::parse(&full_path)?;
+        let refs_data = match quota_service::check_quota(&repo_path, 0) {
+            Ok(()) => repo_manager.get_receive_refs(&full_path)?,
+            Err(e) => {
+                // 已经超出配额时在引用通告阶段就拒绝，客户端显示 remote error
+                warn!("拒绝推送 {}: {}", full_path, e);
+                let mut buf = pkt_line(b"# service=git-receive-pack\n");
+                buf.extend(b"0000");
+                buf.extend(err_line(&e.to_string()));
+                buf
+            }
+        };
+        return Ok(HttpResponse::Ok()
+            .content_type("application/x-git-receive-pack-advertisement")
+            .insert_header(("Cache-Control", "no-cache"))
+            .body(refs_data));
+    }
+
     let refs_data = repo_manager
         .get_refs(&full_path)
         .map_err(|e| actix_web::error::ErrorInternalServerError(e.to_string()))?;
@@ -49,6 +80,155 @@ async fn upload_pack(
         .body(pack_data))
 }
 
+// 单次推送请求体的上限
+const MAX_PUSH_SIZE: u64 = 1024 * 1024 * 1024;
+
+// 把推送的请求体写入临时文件，不在内存中缓存整个 pack
+async fn spool_payload(mut payload: web::Payload) -> Result<(File, u64), Error> {
+    let mut file = tempfile::tempfile()?;
+    let mut size = 0u64;
+    while let Some(chunk) = payload.next().await {
+        let chunk = chunk?;
+        size += chunk.len() as u64;
+        if size > MAX_PUSH_SIZE {
+            return Err(actix_web::error::ErrorPayloadTooLarge(format!(
+                "推送数据不能超过 {} 字节",
+                MAX_PUSH_SIZE
+            )));
+        }
+        file.write_all(&chunk)?;
+    }
+    file.seek(SeekFrom::Start(0))?;
+    Ok((file, size))
+}
+
+#[post("/{repo_name}/git-receive-pack")]
+async fn receive_pack(
+    repo_name: web::Path<String>,
+    payload: web::Payload,
+    repo_manager: Data<Arc<RepoManager>>,
+) -> Result<HttpResponse, Error> {
+    let repo_name_str = repo_name.into_inner();
+    if !repo_manager.repo_exists(&repo_name_str) {
+        warn!("{}没有找到", repo_name_str);
+        return Ok(HttpResponse::NotFound().body("Repository not found"));
+    }
+    let repo_path = RepoPath::parse(&repo_name_str)?;
+    let (mut input, size) = spool_payload(payload).await?;
+
+    // actix 的 Error 不能跨线程传递，子线程中先转成字符串
+    let result = web::block(move || -> Result<Vec<u8>, String> {
+        let commands = read_command_section(&mut input).map_err(|e| e.to_string())?;
+
+        // 以请求体大小估算本次推送新增的数据量
+        let rejection = match quota_service::check_quota(&repo_path, size) {
+            Ok(()) => protected_violation(&repo_manager, &repo_name_str, &commands, &mut input),
+            Err(e) => Some(e.to_string()),
+        };
+        input.seek(SeekFrom::Start(0)).map_err(|e| e.to_string())?;
+        match rejection {
+            None => {
+                // 比较推送前后的引用，全部被拒绝时不需要推送到下游镜像
+                let snapshot = || {
+                    repo_manager
+                        .get_repo(&repo_name_str)
+                        .ok()
+                        .and_then(|repo| mirror_service::snapshot_refs(&repo, &["refs/"]).ok())
+                };
+                let before = snapshot();
+                let result = repo_manager
+                    .handle_receive_pack(&repo_name_str, input)
+                    .map_err(|e| e.to_string())?;
+                if before.is_none() || before != snapshot() {
+                    push_mirror_service::schedule(&repo_path);
+                }
+                Ok(result)
+            }
+            Some(reason) => {
+                warn!("拒绝推送 {}: {}", repo_name_str, reason);
+                Ok(reject_receive_pack(&commands, &reason))
+            }
+        }
+    })
+    .await?
+    .map_err(actix_web::error::ErrorInternalServerError)?;
+    Ok(HttpResponse::Ok()
+        .content_type("application/x-git-receive-pack-result")
+        .body(result))
+}
+
+// 推送中删除或强制更新受保护分支时返回拒绝原因，读不到仓库或设置时同样拒绝
+//
+// 新提交还在推送的 pack 中，有受保护分支的更新时先把 pack 索引到临时目录再判断是否快进。
+fn protected_violation(
+    repo_manager: &RepoManager,
+    repo_name: &str,
+    commands: &[u8],
+    input: &mut File,
+) -> Option<String> {
+    let repo = match repo_manager.get_repo(repo_name) {
+        Ok(repo) => repo,
+        Err(e) => return Some(format!("无法读取仓库: {}", e)),
+    };
+    let (updates, _) = parse_receive_commands(commands);
+    let mut protected = Vec::new();
+    for update in &updates {
+        let Some(branch) = update.refname.strip_prefix("refs/heads/") else {
+            continue;
+        };
+        match settings_service::is_protected_branch(&repo, branch) {
+            Ok(true) => protected.push((branch, update)),
+            Ok(false) => {}
+            Err(e) => return Some(format!("无法读取受保护分支设置: {}", e)),
+        }
+    }
+    if let Some((branch, _)) = protected.iter().find(|(_, update)| update.is_delete()) {
+        return Some(format!("分支 {} 受保护，不能删除", branch));
+    }
+    protected.retain(|(_, update)| !update.is_create());
+    if protected.is_empty() {
+        return None;
+    }
+
+    let _pack = match index_pushed_pack(&repo, input, commands.len() as u64) {
+        Ok(pack) => pack,
+        Err(e) => return Some(format!("无法解析推送的数据: {}", e)),
+    };
+    for (branch, update) in protected {
+        let fast_forward = Oid::from_str(&update.old)
+            .and_then(|old| Ok((old, Oid::from_str(&update.new)?)))
+            .and_then(|(old, new)| Ok(old == new || repo.graph_descendant_of(new, old)?));
+        match fast_forward {
+            Ok(true) => {}
+            Ok(false) => return Some(format!("分支 {} 受保护，不能强制推送", branch)),
+            Err(e) => return Some(format!("无法检查分支 {} 的更新: {}", branch, e)),
+        }
+    }
+    None
+}
+
+// 把命令之后的 pack 索引到临时目录，并作为备用对象库加入 repo，返回的目录在检查结束前不能删除
+fn index_pushed_pack(
+    repo: &Repository,
+    input: &mut File,
+    offset: u64,
+) -> Result<Option<TempDir>, Box<dyn std::error::Error>> {
+    input.seek(SeekFrom::Start(offset))?;
+    let dir = tempfile::tempdir()?;
+    let pack_dir = dir.path().join("pack");
+    std::fs::create_dir(&pack_dir)?;
+    let odb = repo.odb()?;
+    // 传入仓库的对象库以补全 thin pack 引用的对象
+    let mut indexer = Indexer::new(Some(&odb), &pack_dir, 0o644, true)?;
+    // 只更新到已有提交时没有 pack
+    if std::io::copy(input, &mut indexer)? == 0 {
+        return Ok(None);
+    }
+    indexer.commit()?;
+    odb.add_disk_alternate(dir.path().to_str().ok_or("临时目录路径不是 UTF-8")?)?;
+    Ok(Some(dir))
+}
+
 #[get("/{repo_name}/HEAD")]
 async fn head_ref(
     repo_name: web::Path<String>,
@@ -0,0 +1,471 @@
+use crate::repo::barerepo_manager::RepoManager;
+use crate::repo::repo_path::RepoPath;
+use crate::service::browse_service::{
+    self, BlameQuery, BrowseError, CommitDetail, CommitWithDiff, LogQuery, parse_time,
+};
+use actix_files::HttpRange;
+use actix_web::http::header;
+use actix_web::web::{Bytes, Data};
+use actix_web::{Error, HttpRequest, HttpResponse, get, web};
+use futures::stream;
+use log::{info, warn};
+use serde::Deserialize;
+use std::io::Read;
+use std::sync::Arc;
+
+// 仓库中的 HTML、SVG 等文件不能在本站点下执行脚本
+const RAW_CSP: &str = "default-src 'none'; style-src 'unsafe-inline'; sandbox";
+
+fn header_str(req: &HttpRequest, name: header::HeaderName) -> Option<&str> {
+    req.headers()
+        .get(name)
+        .and_then(|value| value.to_str().ok())
+}
+
+// 原始文件每次发送的块大小
+const RAW_CHUNK: usize = 64 * 1024;
+
+// 读出对象后决定的响应，`start..end` 是要发送的内容区间
+enum RawResponse {
+    NotModified {
+        etag: String,
+    },
+    Content {
+        etag: String,
+        content_type: String,
+        size: usize,
+        start: usize,
+        end: usize,
+        partial: bool,
+    },
+    RangeNotSatisfiable {
+        size: usize,
+    },
+}
+
+// 按条件请求头和 Range 决定响应方式
+fn raw_response(
+    blob: &browse_service::RawBlob,
+    file_path: &str,
+    if_none_match: Option<&str>,
+    range: Option<&str>,
+    if_range: Option<&str>,
+) -> RawResponse {
+    let etag = format!("\"{}\"", blob.blob.id());
+    if if_none_match.is_some_and(|tags| {
+        tags.split(',')
+            .any(|tag| tag.trim() == etag || tag.trim() == "*")
+    }) {
+        return RawResponse::NotModified { etag };
+    }
+
+    let content_type = if blob.symlink {
+        "text/plain; charset=utf-8".to_string()
+    } else {
+        browse_service::content_type(file_path, blob.content())
+    };
+    let size = blob.content().len();
+    // If-Range 与当前版本不一致时返回完整内容
+    let Some(range) = range.filter(|_| if_range.is_none_or(|tag| tag == etag)) else {
+        return RawResponse::Content {
+            etag,
+            content_type,
+            size,
+            start: 0,
+            end: size,
+            partial: false,
+        };
+    };
+    match HttpRange::parse(range, size as u64)
+        .ok()
+        .and_then(|ranges| ranges.first().copied())
+    {
+        Some(range) => RawResponse::Content {
+            etag,
+            content_type,
+            size,
+            start: range.start as usize,
+            end: (range.start + range.length) as usize,
+            partial: true,
+        },
+        None => RawResponse::RangeNotSatisfiable { size },
+    }
+}
+
+/// 从裸仓库的对象库读取任意分支、标签或提交中的文件
+///
+/// ETag 为 blob 的 OID，支持 If-None-Match、Range 和 If-Range（只返回第一个区间）。
+#[get("/repos/{name}/raw/{spec:.*}")]
+async fn get_raw(
+    req: HttpRequest,
+    path: web::Path<(String, String)>,
+    repo_manager: Data<Arc<RepoManager>>,
+) -> Result<HttpResponse, Error> {
+    let (name, spec) = path.into_inner();
+    let repo_name = RepoPath::parse(&name)?;
+    let repo = repo_manager.get_repo(&repo_name.bare_name())?;
+    let if_none_match = header_str(&req, header::IF_NONE_MATCH).map(str::to_string);
+    let range = header_str(&req, header::RANGE).map(str::to_string);
+    let if_range = header_str(&req, header::IF_RANGE).map(str::to_string);
+
+    // 在阻塞线程中读取对象，内容按块从 libgit2 载入的对象中发送，不复制整个文件
+    let (plan_sender, plan_receiver) =
+        tokio::sync::oneshot::channel::<Result<RawResponse, BrowseError>>();
+    let (sender, receiver) = tokio::sync::mpsc::channel::<std::io::Result<Bytes>>(4);
+    tokio::task::spawn_blocking(move || {
+        let blob = browse_service::split_ref_path(&repo, &spec).and_then(|(commit, file_path)| {
+            Ok((
+                browse_service::read_blob(&repo, &commit, &file_path)?,
+                file_path,
+            ))
+        });
+        let (blob, file_path) = match blob {
+            Ok(blob) => blob,
+            Err(e) => {
+                let _ = plan_sender.send(Err(e));
+                return;
+            }
+        };
+        let plan = raw_response(
+            &blob,
+            &file_path,
+            if_none_match.as_deref(),
+            range.as_deref(),
+            if_range.as_deref(),
+        );
+        let body = match &plan {
+            RawResponse::Content { start, end, .. } => *start..*end,
+            _ => 0..0,
+        };
+        if plan_sender.send(Ok(plan)).is_err() {
+            return;
+        }
+        for chunk in blob.content()[body].chunks(RAW_CHUNK) {
+            // 客户端断开后停止发送
+            if sender
+                .blocking_send(Ok(Bytes::copy_from_slice(chunk)))
+                .is_err()
+            {
+                return;
+            }
+        }
+    });
+
+    let plan = plan_receiver
+        .await
+        .map_err(|_| actix_web::error::ErrorInternalServerError("读取文件的任务异常退出"))??;
+    match plan {
+        RawResponse::NotModified { etag } => Ok(HttpResponse::NotModified()
+            .insert_header((header::ETAG, etag))
+            .finish()),
+        RawResponse::RangeNotSatisfiable { size } => Ok(HttpResponse::RangeNotSatisfiable()
+            .insert_header((header::CONTENT_RANGE, format!("bytes */{}", size)))
+            .finish()),
+        RawResponse::Content {
+            etag,
+            content_type,
+            size,
+            start,
+            end,
+            partial,
+        } => {
+            let mut response = HttpResponse::Ok();
+            response
+                .insert_header((header::CONTENT_TYPE, content_type))
+                .insert_header((header::ETAG, etag))
+                .insert_header((header::ACCEPT_RANGES, "bytes"))
+                // 分支会移动，每次都需要用 ETag 重新验证
+                .insert_header((header::CACHE_CONTROL, "no-cache"))
+                .insert_header((header::CONTENT_SECURITY_POLICY, RAW_CSP))
+                .insert_header((header::X_CONTENT_TYPE_OPTIONS, "nosniff"))
+                .no_chunking((end - start) as u64);
+            if partial {
+                response
+                    .status(actix_web::http::StatusCode::PARTIAL_CONTENT)
+                    .insert_header((
+                        header::CONTENT_RANGE,
+                        format!("bytes {}-{}/{}", start, end - 1, size),
+                    ));
+            }
+            let body = stream::unfold(receiver, |mut receiver| async move {
+                receiver.recv().await.map(|chunk| (chunk, receiver))
+            });
+            Ok(response.streaming(body))
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct TreeQuery {
+    // 是否附带每个条目的最后提交，需要遍历历史
+    #[serde(default)]
+    last_commit: bool,
+}
+
+/// 列出任意版本中的目录，路径为空时是根目录
+#[get("/repos/{name}/tree/{spec:.*}")]
+async fn get_tree(
+    path: web::Path<(String, String)>,
+    query: web::Query<TreeQuery>,
+    repo_manager: Data<Arc<RepoManager>>,
+) -> Result<HttpResponse, Error> {
+    let (name, spec) = path.into_inner();
+    let repo_name = RepoPath::parse(&name)?;
+    let repo = repo_manager.get_repo(&repo_name.bare_name())?;
+    let last_commit = query.last_commit;
+    // last_commit 需要遍历历史，与其他读取历史的接口一样放到阻塞线程中
+    let listing = web::block(move || {
+        let (commit, dir) = browse_service::split_ref_path(&repo, &spec)?;
+        browse_service::list_tree(&repo, &commit, &dir, last_commit)
+    })
+    .await?
+    .map_err(Error::from)?;
+    Ok(HttpResponse::Ok().json(listing))
+}
+
+#[derive(Deserialize)]
+struct CommitsQuery {
+    #[serde(rename = "ref")]
+    rev: Option<String>,
+    path: Option<String>,
+    since: Option<String>,
+    until: Option<String>,
+    author: Option<String>,
+    // 上一页返回的 next_cursor
+    page: Option<String>,
+    per_page: Option<usize>,
+    // 验证提交签名，默认只返回签名格式
+    #[serde(default)]
+    verify: bool,
+}
+
+/// 提交历史，默认从 HEAD 开始，使用游标分页
+#[get("/repos/{name}/commits")]
+async fn list_commits(
+    name: web::Path<String>,
+    query: web::Query<CommitsQuery>,
+    repo_manager: Data<Arc<RepoManager>>,
+) -> Result<HttpResponse, Error> {
+    let repo_name = RepoPath::parse(&name)?;
+    let repo = repo_manager.get_repo(&repo_name.bare_name())?;
+    let query = query.into_inner();
+    let log_query = LogQuery {
+        rev: query.rev.unwrap_or_else(|| "HEAD".to_string()),
+        path: query.path,
+        since: query.since.as_deref().map(parse_time).transpose()?,
+        until: query.until.as_deref().map(parse_time).transpose()?,
+        author: query.author.filter(|author| !author.is_empty()),
+        cursor: query.page.filter(|page| !page.is_empty()),
+        per_page: query.per_page,
+        verify: query.verify,
+    };
+    let page = web::block(move || browse_service::list_commits(&repo, &log_query))
+        .await?
+        .map_err(Error::from)?;
+    Ok(HttpResponse::Ok().json(page))
+}
+
+#[derive(Deserialize)]
+struct DiffQuery {
+    // json（默认）或 diff
+    format: Option<String>,
+    // 每个 hunk 的上下文行数
+    context: Option<u32>,
+}
+
+const DIFF_CONTENT_TYPE: &str = "text/x-diff; charset=utf-8";
+
+// ?format=diff 或 Accept: text/x-diff 时返回统一格式的文本
+fn wants_text_diff(req: &HttpRequest, query: &DiffQuery) -> bool {
+    match query.format.as_deref() {
+        Some(format) => matches!(format, "diff" | "patch"),
+        None => {
+            header_str(req, header::ACCEPT).is_some_and(|accept| accept.contains("text/x-diff"))
+        }
+    }
+}
+
+fn json_body<T: serde::Serialize>(value: &T) -> (Vec<u8>, &'static str) {
+    (
+        serde_json::to_vec(value).unwrap_or_default(),
+        "application/json",
+    )
+}
+
+/// 单个提交的信息和相对第一个父提交的差异
+#[get("/repos/{name}/commits/{sha}")]
+async fn get_commit(
+    req: HttpRequest,
+    path: web::Path<(String, String)>,
+    query: web::Query<DiffQuery>,
+    repo_manager: Data<Arc<RepoManager>>,
+) -> Result<HttpResponse, Error> {
+    let (name, sha) = path.into_inner();
+    let repo_name = RepoPath::parse(&name)?;
+    let repo = repo_manager.get_repo(&repo_name.bare_name())?;
+    let text = wants_text_diff(&req, &query);
+    let context = query.context.unwrap_or(3);
+    let (body, content_type) = web::block(move || {
+        let commit = browse_service::resolve_commit(&repo, &sha)
+            .ok_or_else(|| BrowseError::NotFound(format!("提交 {} 不存在", sha)))?;
+        let diff = browse_service::commit_diff(&repo, &commit, context, !text)?;
+        if text {
+            let mut body = browse_service::commit_header(&commit).into_bytes();
+            body.extend(browse_service::diff_text(&diff)?);
+            return Ok::<_, BrowseError>((body, DIFF_CONTENT_TYPE));
+        }
+        Ok(json_body(&CommitWithDiff {
+            commit: CommitDetail::from_commit(&repo, &commit, true),
+            diff: browse_service::diff_json(&diff)?,
+        }))
+    })
+    .await??;
+    Ok(HttpResponse::Ok().content_type(content_type).body(body))
+}
+
+/// 比较两个版本：`base...head` 从共同祖先开始，`base..head` 直接比较
+#[get("/repos/{name}/compare/{spec:.*}")]
+async fn compare(
+    req: HttpRequest,
+    path: web::Path<(String, String)>,
+    query: web::Query<DiffQuery>,
+    repo_manager: Data<Arc<RepoManager>>,
+) -> Result<HttpResponse, Error> {
+    let (name, spec) = path.into_inner();
+    let repo_name = RepoPath::parse(&name)?;
+    let repo = repo_manager.get_repo(&repo_name.bare_name())?;
+    let text = wants_text_diff(&req, &query);
+    let context = query.context.unwrap_or(3);
+    let (body, content_type) = web::block(move || {
+        let range = browse_service::compare_range(&repo, &spec)?;
+        let head = range.head.tree()?;
+        let diff = browse_service::diff_trees(&repo, Some(&range.from), &head, context, !text)?;
+        if text {
+            return Ok::<_, BrowseError>((browse_service::diff_text(&diff)?, DIFF_CONTENT_TYPE));
+        }
+        Ok(json_body(&browse_service::comparison(
+            &repo, &range, &diff,
+        )?))
+    })
+    .await??;
+    Ok(HttpResponse::Ok().content_type(content_type).body(body))
+}
+
+#[derive(Deserialize)]
+struct BlameParams {
+    // 行范围，从 1 开始，包含两端
+    start: Option<usize>,
+    end: Option<usize>,
+    #[serde(default)]
+    ignore_whitespace: bool,
+}
+
+/// 追溯文件每一行的最后修改提交，大文件按行范围分批返回
+#[get("/repos/{name}/blame/{spec:.*}")]
+async fn get_blame(
+    path: web::Path<(String, String)>,
+    query: web::Query<BlameParams>,
+    repo_manager: Data<Arc<RepoManager>>,
+) -> Result<HttpResponse, Error> {
+    let (name, spec) = path.into_inner();
+    let repo_name = RepoPath::parse(&name)?;
+    let repo = repo_manager.get_repo(&repo_name.bare_name())?;
+    let blame_query = BlameQuery {
+        start: query.start,
+        end: query.end,
+        ignore_whitespace: query.ignore_whitespace,
+    };
+    let result = web::block(move || {
+        let (commit, file_path) = browse_service::split_ref_path(&repo, &spec)?;
+        browse_service::blame(&repo, &commit, &file_path, &blame_query)
+    })
+    .await??;
+    Ok(HttpResponse::Ok().json(result))
+}
+
+#[derive(Deserialize)]
+struct ArchiveQuery {
+    // 归档内的前缀目录，空字符串表示不加前缀
+    prefix: Option<String>,
+}
+
+// 每次从 git archive 读取的字节数
+const ARCHIVE_CHUNK: usize = 64 * 1024;
+
+/// 下载任意版本的 tar.gz 或 zip 归档，内容由 `git archive` 边生成边返回
+#[get("/repos/{name}/archive/{spec:.*}")]
+async fn get_archive(
+    path: web::Path<(String, String)>,
+    query: web::Query<ArchiveQuery>,
+    repo_manager: Data<Arc<RepoManager>>,
+) -> Result<HttpResponse, Error> {
+    let (name, spec) = path.into_inner();
+    let repo_name = RepoPath::parse(&name)?;
+    let repo = repo_manager.get_repo(&repo_name.bare_name())?;
+    let (rev, format) = browse_service::archive_spec(&spec)?;
+    let commit = browse_service::resolve_commit(&repo, rev)
+        .ok_or_else(|| BrowseError::NotFound(format!("引用 {} 不存在", rev)))?;
+    let prefix = browse_service::archive_prefix(repo_name.name(), rev, query.prefix.as_deref())?;
+    let mut child = browse_service::spawn_archive(&repo, &commit, format, &prefix)?;
+    let filename = format!(
+        "{}.{}",
+        if prefix.is_empty() {
+            rev.replace('/', "-")
+        } else {
+            prefix.trim_end_matches('/').replace('/', "-")
+        },
+        format.extension()
+    );
+    info!("生成归档 {} ({})", filename, commit.id());
+
+    // 在阻塞线程中读取子进程输出，客户端断开后结束子进程
+    let (sender, receiver) = tokio::sync::mpsc::channel::<std::io::Result<Bytes>>(4);
+    tokio::task::spawn_blocking(move || {
+        let mut stdout = child.stdout.take().expect("stdout 已设置为管道");
+        let mut buffer = vec![0u8; ARCHIVE_CHUNK];
+        loop {
+            let chunk = match stdout.read(&mut buffer) {
+                Ok(0) => break,
+                Ok(n) => Ok(Bytes::copy_from_slice(&buffer[..n])),
+                Err(e) => Err(e),
+            };
+            let failed = chunk.is_err();
+            if sender.blocking_send(chunk).is_err() || failed {
+                let _ = child.kill();
+                let _ = child.wait();
+                return;
+            }
+        }
+        match child.wait_with_output() {
+            Ok(output) if output.status.success() => {}
+            // 响应头已经发出，只能中断连接让客户端知道归档不完整
+            Ok(output) => {
+                let stderr = String::from_utf8_lossy(&output.stderr).trim().to_string();
+                warn!("git archive 失败: {}", stderr);
+                let _ = sender.blocking_send(Err(std::io::Error::other(stderr)));
+            }
+            Err(e) => {
+                let _ = sender.blocking_send(Err(e));
+            }
+        }
+    });
+    let body = stream::unfold(receiver, |mut receiver| async move {
+        receiver.recv().await.map(|chunk| (chunk, receiver))
+    });
+    Ok(HttpResponse::Ok()
+        .content_type(format.content_type())
+        .insert_header(header::ContentDisposition::attachment(filename))
+        .streaming(body))
+}
+
+/// 分支列表，附带最后提交和相对默认分支的领先、落后提交数
+#[get("/repos/{name}/branches")]
+async fn list_branches(
+    name: web::Path<String>,
+    repo_manager: Data<Arc<RepoManager>>,
+) -> Result<HttpResponse, Error> {
+    let repo_name = RepoPath::parse(&name)?;
+    let repo = repo_manager.get_repo(&repo_name.bare_name())?;
+    let listing = web::block(move || browse_service::list_branches(&repo)).await??;
+    Ok(HttpResponse::Ok().json(listing))
+}
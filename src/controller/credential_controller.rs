@@ -0,0 +1,22 @@
+use crate::service::credential_service::{self, NewCredential};
+use actix_web::{Error, HttpResponse, delete, get, post, web};
+
+#[get("/credentials")]
+async fn list_credentials() -> Result<HttpResponse, Error> {
+    let credentials = web::block(credential_service::list_credentials).await??;
+    Ok(HttpResponse::Ok().json(credentials))
+}
+
+#[post("/credentials")]
+async fn add_credential(params: web::Json<NewCredential>) -> Result<HttpResponse, Error> {
+    let request = params.into_inner();
+    let info = web::block(move || credential_service::add_credential(request)).await??;
+    Ok(HttpResponse::Created().json(info))
+}
+
+#[delete("/credentials/{id}")]
+async fn delete_credential(id: web::Path<String>) -> Result<HttpResponse, Error> {
+    let id = id.into_inner();
+    web::block(move || credential_service::delete_credential(&id)).await??;
+    Ok(HttpResponse::NoContent().finish())
+}
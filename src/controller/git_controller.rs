@@ -1,14 +1,32 @@
-use crate::controller::barerepo_controller::{head_ref, info_refs, upload_pack};
-use crate::service::git_service;
+use crate::controller::barerepo_controller::{head_ref, info_refs, receive_pack, upload_pack};
+use crate::controller::browse_controller::{
+    compare, get_archive, get_blame, get_commit, get_raw, get_tree, list_branches, list_commits,
+};
+use crate::controller::credential_controller::{
+    add_credential, delete_credential, list_credentials,
+};
+use crate::controller::job_controller::{cancel_job, get_job, job_events, list_jobs};
+use crate::controller::repo_controller::{
+    create_branch, create_commit, create_tag, delete_branch, delete_push_mirror, delete_tag,
+    get_default_branch, get_mirror, get_settings, get_usage, list_mirrors, list_push_mirrors,
+    patch_settings, push_to_mirrors, put_default_branch, put_mirror, put_push_mirror,
+};
+use crate::controller::webhook_controller::receive_hook;
+use crate::repo::barerepo_manager::RepoManager;
+use crate::repo::repo_path::RepoPath;
+use crate::service::git_service::BareSyncResult;
+use crate::service::job_service::{Job, JobKind, JobManager, JobOutcome};
+use crate::service::{
+    browse_service, credential_service, git_service, mirror_service, template_service,
+};
 use actix_files::NamedFile;
 use actix_web::web;
 use actix_web::{Error, HttpRequest, HttpResponse, Responder, get, post};
-use anyhow::{Result, anyhow};
+use anyhow::Result;
 use log::info;
-use log::warn;
-use secrecy::Secret;
 use serde::{Deserialize, Serialize};
 use std;
+use std::sync::Arc;
 
 #[derive(Debug, Deserialize, Serialize)]
 struct CloneRequest {
@@ -19,6 +37,12 @@ struct CloneRequest {
 struct PullRequest {
     // url: String,
     path: String,
+    // 镜像模式：把上游所有分支和标签直接抓取到裸仓库
+    #[serde(default)]
+    mirror: bool,
+    // 镜像模式下删除上游已不存在的引用
+    #[serde(default)]
+    prune: bool,
 }
 
 // 定义根路径处理器
@@ -27,50 +51,125 @@ async fn hello() -> impl Responder {
     HttpResponse::Ok().body("Hello World!")
 }
 
+// 任务已加入后台队列，进度通过 GET /jobs/{id} 查询
+fn accepted(job: Job) -> HttpResponse {
+    HttpResponse::Accepted()
+        .insert_header(("Location", format!("/jobs/{}", job.id)))
+        .json(job)
+}
+
+fn to_json<T: Serialize>(value: &T) -> serde_json::Value {
+    serde_json::to_value(value).unwrap_or_default()
+}
+
 #[post("/clone_pri")]
-async fn clone_pri(params: web::Json<CloneRequest>) -> impl Responder {
+async fn clone_pri(
+    params: web::Json<CloneRequest>,
+    jobs: web::Data<Arc<JobManager>>,
+) -> impl Responder {
     let request = params.into_inner();
-    match get_token() {
-        Ok(token) => {
-            let path = std::path::Path::new(&request.path);
-            match git_service::clone_with_token(&request.url, path, token) {
-                Ok(_) => HttpResponse::Ok().body("Repository cloned successfully"),
-                Err(e) => HttpResponse::InternalServerError()
-                    .body(format!("Failed to clone repository: {}", e)),
-            }
+    let repo_name = match RepoPath::parse(&request.path) {
+        Ok(repo_name) => repo_name,
+        Err(e) => return HttpResponse::BadRequest().body(e.to_string()),
+    };
+    let job = jobs.submit(JobKind::Clone, repo_name.to_string(), move |context| {
+        match git_service::clone_with_credentials(&request.url, &repo_name, Some(context)) {
+            Ok(_) => JobOutcome::Succeeded(serde_json::json!({
+                "repo": repo_name.to_string(),
+                "message": "Repository cloned successfully",
+            })),
+            Err(e) => JobOutcome::failed(format!("Failed to clone repository: {}", e)),
         }
-        Err(e) => HttpResponse::InternalServerError().body(format!("Failed to get token: {}", e)),
-    }
+    });
+    accepted(job)
 }
 
 #[post("/pull_pri")]
-async fn pull_pri(params: web::Json<PullRequest>) -> impl Responder {
+async fn pull_pri(
+    params: web::Json<PullRequest>,
+    jobs: web::Data<Arc<JobManager>>,
+) -> impl Responder {
     let request = params.into_inner();
-    match get_token() {
-        Ok(token) => match git_service::pull_with_token(&request.path, token) {
-            Ok(meassage) => HttpResponse::Ok().body(meassage),
-            Err(e) => HttpResponse::InternalServerError().body(format!("拉取更新失败：{}", e)),
-        },
-        Err(e) => HttpResponse::InternalServerError().body(format!("获取token失败:{}", e)),
-    }
+    let repo_name = match RepoPath::parse(&request.path) {
+        Ok(repo_name) => repo_name,
+        Err(e) => return HttpResponse::BadRequest().body(e.to_string()),
+    };
+    let job = jobs.submit(JobKind::Pull, repo_name.to_string(), move |context| {
+        if request.mirror {
+            return match mirror_service::mirror_with_credentials(
+                &repo_name,
+                request.prune,
+                Some(context),
+            ) {
+                // 裸仓库已更新，但工作仓库没有跟上
+                Ok(report) if report.warning.is_some() => JobOutcome::Failed {
+                    error: report.warning.clone().unwrap_or_default(),
+                    result: Some(to_json(&report)),
+                },
+                Ok(report) => JobOutcome::Succeeded(to_json(&report)),
+                Err(e) => JobOutcome::failed(format!("镜像同步失败：{}", e)),
+            };
+        }
+        match git_service::pull_with_credentials(&repo_name, Some(context)) {
+            // 分叉后保留了本地提交或者存在冲突
+            Ok(outcome) if outcome.needs_attention() => JobOutcome::Failed {
+                error: outcome.message.clone(),
+                result: Some(to_json(&outcome)),
+            },
+            // 工作仓库已更新，但裸仓库没有同步成功
+            Ok(outcome) if matches!(outcome.bare_sync, BareSyncResult::Failed { .. }) => {
+                JobOutcome::Failed {
+                    error: "同步裸仓库失败".to_string(),
+                    result: Some(to_json(&outcome)),
+                }
+            }
+            Ok(outcome) => JobOutcome::Succeeded(to_json(&outcome)),
+            Err(e) => JobOutcome::failed(format!("拉取更新失败：{}", e)),
+        }
+    });
+    accepted(job)
 }
 
 #[derive(Debug, Deserialize, Serialize)]
 struct BranchRequest {
     url: String,       // 远程仓库地址
     repo_name: String, // 仓库名
+    // 按通配符过滤分支和标签，例如 "release/*"
+    #[serde(default)]
+    pattern: Option<String>,
 }
 
 #[post("/fetch_remote_branches")]
-async fn fetch_remote_branches(params: web::Json<BranchRequest>) -> impl Responder {
+async fn fetch_remote_branches(
+    params: web::Json<BranchRequest>,
+    jobs: web::Data<Arc<JobManager>>,
+) -> impl Responder {
     let request = params.into_inner();
-    match get_token() {
-        Ok(token) => match git_service::fetch_remote_branches(&request.url, token) {
-            Ok(branches) => HttpResponse::Ok().json(branches),
-            Err(e) => HttpResponse::InternalServerError().body(format!("获取远程分支失败: {}", e)),
+    // 仓库名只用于选择凭据，允许为空
+    let repo_name = match request.repo_name.as_str() {
+        "" => None,
+        name => match RepoPath::parse(name) {
+            Ok(repo_name) => Some(repo_name),
+            Err(e) => return HttpResponse::BadRequest().body(e.to_string()),
         },
-        Err(e) => HttpResponse::InternalServerError().body(format!("获取token失败: {}", e)),
+    };
+    if git_service::is_local_url(&request.url) {
+        return HttpResponse::BadRequest().body("不支持本地路径或 file:// 地址");
     }
+    // 任务列表中不展示地址里的用户名和令牌
+    let target = credential_service::redact_url(&request.url);
+    // 列出引用没有传输进度回调，任务开始执行后无法取消，只能在排队时取消
+    let job = jobs.submit(JobKind::FetchRemoteBranches, target, move |_| {
+        match git_service::fetch_remote_branches(
+            &request.url,
+            repo_name.as_ref(),
+            request.pattern.as_deref(),
+        ) {
+            Ok(refs) => JobOutcome::Succeeded(to_json(&refs)),
+            Err(e) => JobOutcome::failed(format!("获取远程分支失败: {}", e)),
+        }
+    });
+    accepted(job)
 }
 
 #[post("/clone_pub")]
@@ -78,7 +177,13 @@ async fn clone_pub(params: web::Json<CloneRequest>) -> impl Responder {
     let request: CloneRequest = params.into_inner();
     print!("request:{:?}", request);
     let url = &request.url;
-    let _repo = match git2::Repository::clone(url, request.path) {
+    let repo_path = match RepoPath::parse(&request.path)
+        .and_then(|name| name.work_dir(std::path::Path::new("test_repos")))
+    {
+        Ok(path) => path,
+        Err(e) => return HttpResponse::BadRequest().body(e.to_string()),
+    };
+    let _repo = match git2::Repository::clone(url, repo_path) {
         Ok(repo) => repo,
         Err(e) => panic!("failed to clone: {}", e),
     };
@@ -92,32 +197,45 @@ async fn search_all_repo() -> Result<HttpResponse, Error> {
     Ok(HttpResponse::Ok().json(result))
 }
 
-#[derive(Deserialize)]
-struct BranchQuery {
-    repo_name: String,
-}
+// 旧接口，与 GET /repos/{name}/branches 返回相同的内容
 #[get("/search_all_branch/{repo_name}")]
-async fn search_all_branch(params: web::Query<BranchQuery>) -> Result<HttpResponse, Error> {
-    let branch_query = params.into_inner();
-    print!("{:?}", branch_query.repo_name);
-    let list = git_service::list_branches(&branch_query.repo_name);
-
-    Ok(HttpResponse::Ok().json(format!("{:?}", list)))
+async fn search_all_branch(
+    name: web::Path<String>,
+    repo_manager: web::Data<Arc<RepoManager>>,
+) -> Result<HttpResponse, Error> {
+    let repo_name = RepoPath::parse(&name)?;
+    let repo = repo_manager.get_repo(&repo_name.bare_name())?;
+    let listing = web::block(move || browse_service::list_branches(&repo)).await??;
+    Ok(HttpResponse::Ok().json(listing))
 }
 
 #[derive(Deserialize)]
 struct RepoQuery {
     repo_name: String,
+    // 可选的服务端模板名
+    template: Option<String>,
 }
 
 #[get("/init_repo")]
 async fn init_repo(repo_params: web::Query<RepoQuery>) -> impl Responder {
-    match git_service::init_repo(repo_params.repo_name.clone()) {
+    let repo_name = match RepoPath::parse(&repo_params.repo_name) {
+        Ok(repo_name) => repo_name,
+        Err(e) => return HttpResponse::BadRequest().body(e.to_string()),
+    };
+    match git_service::init_repo(&repo_name, repo_params.template.as_deref()) {
         Ok(_) => HttpResponse::Ok().json("Repository initialized successfully"),
         Err(e) => HttpResponse::InternalServerError()
             .body(format!("Failed to initialize repository: {}", e)),
     }
 }
+#[get("/templates")]
+async fn list_templates() -> impl Responder {
+    match template_service::list_templates() {
+        Ok(templates) => HttpResponse::Ok().json(templates),
+        Err(e) => HttpResponse::InternalServerError().body(format!("获取模板列表失败: {}", e)),
+    }
+}
+
 #[derive(Debug, Deserialize, Serialize)]
 pub struct SepFileRequest {
     pub repo_name: String,
@@ -147,13 +265,13 @@ async fn get_specify_file(req: HttpRequest, params: web::Query<SepFileRequest>)
                 actix_web::http::header::ContentDisposition::attachment(filename),
             );
             info!("返回文件");
-            return file.into_response(&req);
+            file.into_response(&req)
         }
         Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
             info!("文件出错");
-            return HttpResponse::InternalServerError().body(e.to_string());
+            HttpResponse::InternalServerError().body(e.to_string())
         }
-        Err(_) => return HttpResponse::InternalServerError().body("Failed to read file"),
+        Err(_) => HttpResponse::InternalServerError().body("Failed to read file"),
     }
 }
 
@@ -168,35 +286,43 @@ pub fn path_config(service_config: &mut web::ServiceConfig) {
         .service(search_all_repo)
         .service(search_all_branch)
         .service(init_repo)
+        .service(list_templates)
         .service(upload_pack)
+        .service(receive_pack)
         .service(head_ref)
-        .service(info_refs);
+        .service(info_refs)
+        .service(get_default_branch)
+        .service(put_default_branch)
+        .service(get_usage)
+        .service(get_settings)
+        .service(patch_settings)
+        .service(list_mirrors)
+        .service(get_mirror)
+        .service(put_mirror)
+        .service(list_push_mirrors)
+        .service(put_push_mirror)
+        .service(delete_push_mirror)
+        .service(push_to_mirrors)
+        .service(get_raw)
+        .service(get_tree)
+        .service(list_commits)
+        .service(get_commit)
+        .service(compare)
+        .service(get_blame)
+        .service(get_archive)
+        .service(list_branches)
+        .service(create_branch)
+        .service(delete_branch)
+        .service(create_tag)
+        .service(delete_tag)
+        .service(create_commit)
+        .service(list_jobs)
+        .service(get_job)
+        .service(job_events)
+        .service(cancel_job)
+        .service(receive_hook)
+        .service(list_credentials)
+        .service(add_credential)
+        .service(delete_credential);
     service_config.service(stu_scope);
 }
-
-/// 安全获取访问令牌
-fn get_token() -> Result<Secret<String>> {
-    // 1. 尝试从环境变量获取
-    if let Ok(token) = std::env::var("GIT_TOKEN") {
-        return Ok(Secret::new(token));
-    }
-
-    // 2. 尝试从 .env 文件获取（开发环境）
-    #[cfg(debug_assertions)]
-    {
-        dotenv::dotenv().ok();
-        if let Ok(token) = std::env::var("GIT_TOKEN") {
-            warn!("⚠️ 从 .env 文件获取令牌 - 仅限开发环境使用");
-            return Ok(Secret::new(token));
-        }
-    }
-    // 3. 所有尝试失败
-    Err(anyhow!("无法获取访问令牌。请设置 GIT_TOKEN 环境变量"))
-}
-
-#[derive(Deserialize, Serialize)]
-pub struct FormParams {
-    pub repo_name: String,
-    pub branch_name: String,
-    pub commit_message: String,
-}
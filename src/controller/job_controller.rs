@@ -0,0 +1,78 @@
+use crate::service::job_service::{JobEvent, JobManager};
+use actix_web::web::{Bytes, Data};
+use actix_web::{Error, HttpResponse, get, post, web};
+use futures::stream::{self, StreamExt};
+use serde::Serialize;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::broadcast::error::RecvError;
+
+// 长时间没有事件时发送注释行，防止代理断开连接
+const KEEP_ALIVE: Duration = Duration::from_secs(15);
+
+#[get("/jobs")]
+async fn list_jobs(jobs: Data<Arc<JobManager>>) -> HttpResponse {
+    HttpResponse::Ok().json(jobs.list())
+}
+
+#[get("/jobs/{id}")]
+async fn get_job(
+    id: web::Path<String>,
+    jobs: Data<Arc<JobManager>>,
+) -> Result<HttpResponse, Error> {
+    Ok(HttpResponse::Ok().json(jobs.get(&id)?))
+}
+
+#[post("/jobs/{id}/cancel")]
+async fn cancel_job(
+    id: web::Path<String>,
+    jobs: Data<Arc<JobManager>>,
+) -> Result<HttpResponse, Error> {
+    Ok(HttpResponse::Ok().json(jobs.cancel(&id)?))
+}
+
+// 一条 SSE 消息：事件名加一行 JSON 数据
+fn sse_frame<T: Serialize>(event: &str, data: &T) -> Bytes {
+    let data = serde_json::to_string(data).unwrap_or_default();
+    Bytes::from(format!("event: {}\ndata: {}\n\n", event, data))
+}
+
+/// 以 Server-Sent Events 推送任务进度
+///
+/// 先发送一次当前状态（`state`），之后推送 `progress`（传输进度）、
+/// `message`（远端 sideband 输出）和 `state`（开始、结束），任务结束后关闭连接。
+#[get("/jobs/{id}/events")]
+async fn job_events(
+    id: web::Path<String>,
+    jobs: Data<Arc<JobManager>>,
+) -> Result<HttpResponse, Error> {
+    let (job, receiver) = jobs.subscribe(&id)?;
+    let first = stream::once(async move { Ok::<_, Error>(sse_frame("state", &job)) });
+    let events = stream::unfold(receiver, |receiver| async move {
+        let mut receiver = receiver?;
+        loop {
+            let event = match tokio::time::timeout(KEEP_ALIVE, receiver.recv()).await {
+                Ok(Ok(event)) => event,
+                // 落后太多时跳过丢失的事件，后续的进度事件包含完整数值
+                Ok(Err(RecvError::Lagged(_))) => continue,
+                Ok(Err(RecvError::Closed)) => return None,
+                Err(_) => {
+                    return Some((Ok(Bytes::from_static(b": keep-alive\n\n")), Some(receiver)));
+                }
+            };
+            let (frame, finished) = match &event {
+                JobEvent::State(job) => (sse_frame("state", job), job.state.is_finished()),
+                JobEvent::Progress(progress) => (sse_frame("progress", progress), false),
+                JobEvent::Message(message) => (
+                    sse_frame("message", &serde_json::json!({ "message": message })),
+                    false,
+                ),
+            };
+            return Some((Ok(frame), (!finished).then_some(receiver)));
+        }
+    });
+    Ok(HttpResponse::Ok()
+        .content_type("text/event-stream")
+        .insert_header(("Cache-Control", "no-cache"))
+        .streaming(first.chain(events)))
+}
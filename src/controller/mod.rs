@@ -1,2 +1,7 @@
 pub mod barerepo_controller;
+pub mod browse_controller;
+pub mod credential_controller;
 pub mod git_controller;
+pub mod job_controller;
+pub mod repo_controller;
+pub mod webhook_controller;
@@ -0,0 +1,470 @@
+use crate::auth::token_auth::AuthUser;
+use crate::repo::barerepo_manager::RepoManager;
+use crate::repo::repo_path::RepoPath;
+use crate::service::browse_service::CommitDetail;
+use crate::service::commit_service::{
+    self, ActionKind, CommitError, CommitRequest, FileAction, FileChange, NewCommit,
+};
+use crate::service::mirror_scheduler::{MirrorScheduler, MirrorStatus};
+use crate::service::mirror_service::{self, MirrorConfig};
+use crate::service::push_mirror_service::{self, PushMirrorConfig};
+use crate::service::ref_service::{self, CreateBranch, CreateTag, RefError};
+use crate::service::settings_service::{self, SettingsPatch};
+use crate::service::{git_service, quota_service};
+use actix_multipart::Multipart;
+use actix_web::http::header;
+use actix_web::web::Data;
+use actix_web::{Error, HttpRequest, HttpResponse, delete, get, patch, post, put, web};
+use futures::StreamExt;
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+
+#[derive(Debug, Deserialize, Serialize)]
+struct DefaultBranch {
+    branch: String,
+}
+
+#[get("/repos/{name}/default-branch")]
+async fn get_default_branch(
+    name: web::Path<String>,
+    repo_manager: Data<Arc<RepoManager>>,
+) -> Result<HttpResponse, Error> {
+    let repo_name = RepoPath::parse(&name)?;
+    let branch = repo_manager.get_default_branch(&repo_name.bare_name())?;
+    Ok(HttpResponse::Ok().json(DefaultBranch { branch }))
+}
+
+#[get("/repos/{name}/usage")]
+async fn get_usage(name: web::Path<String>) -> Result<HttpResponse, Error> {
+    let repo_name = RepoPath::parse(&name)?;
+    let usage = quota_service::repo_usage(&repo_name)
+        .map_err(|e| actix_web::error::ErrorInternalServerError(e.to_string()))?;
+    Ok(HttpResponse::Ok().json(usage))
+}
+
+#[get("/repos/{name}/settings")]
+async fn get_settings(
+    name: web::Path<String>,
+    repo_manager: Data<Arc<RepoManager>>,
+) -> Result<HttpResponse, Error> {
+    let repo_name = RepoPath::parse(&name)?;
+    let repo = repo_manager.get_repo(&repo_name.bare_name())?;
+    Ok(HttpResponse::Ok().json(settings_service::read_settings(&repo)?))
+}
+
+#[patch("/repos/{name}/settings")]
+async fn patch_settings(
+    name: web::Path<String>,
+    params: web::Json<SettingsPatch>,
+    repo_manager: Data<Arc<RepoManager>>,
+) -> Result<HttpResponse, Error> {
+    let repo_name = RepoPath::parse(&name)?;
+    let repo = repo_manager.get_repo(&repo_name.bare_name())?;
+    let settings = settings_service::update_settings(&repo, &params)?;
+    Ok(HttpResponse::Ok().json(settings))
+}
+
+#[put("/repos/{name}/default-branch")]
+async fn put_default_branch(
+    name: web::Path<String>,
+    params: web::Json<DefaultBranch>,
+    repo_manager: Data<Arc<RepoManager>>,
+) -> Result<HttpResponse, Error> {
+    let repo_name = RepoPath::parse(&name)?;
+    let request = params.into_inner();
+    repo_manager.set_default_branch(&repo_name.bare_name(), &request.branch)?;
+
+    // 工作仓库跟随默认分支，之后 pull 拉取的就是新的默认分支
+    // 默认分支此时已经更新，检出失败只作为警告返回
+    let branch = request.branch.clone();
+    let warning = web::block(move || {
+        match git_service::checkout_work_branch(&repo_name, &branch) {
+            Ok(true) => info!("工作仓库 {} 已切换到 {}", repo_name, branch),
+            Ok(false) => {}
+            Err(e) => {
+                warn!("工作仓库切换分支失败: {}", e);
+                return Some(format!("默认分支已更新，但工作仓库切换失败: {}", e));
+            }
+        }
+        None
+    })
+    .await?;
+    Ok(HttpResponse::Ok().json(WithWarning {
+        body: request,
+        warning,
+    }))
+}
+
+// 裸仓库中的引用变化后同步到工作仓库，并触发推送镜像
+//
+// 引用此时已经更新，工作仓库同步失败只返回警告，由调用方附在成功响应中。
+fn after_ref_change(
+    repo_name: &RepoPath,
+    refname: &str,
+    target: Option<git2::Oid>,
+) -> Option<String> {
+    push_mirror_service::schedule(repo_name);
+    match git_service::update_work_ref(repo_name, refname, target, false) {
+        Ok(_) => None,
+        Err(e) => {
+            warn!("工作仓库同步引用 {} 失败: {}", refname, e);
+            Some(format!(
+                "{} 已在裸仓库中更新，但工作仓库同步失败: {}",
+                refname, e
+            ))
+        }
+    }
+}
+
+/// 附带警告的成功响应，没有警告时与原响应体相同
+#[derive(Serialize)]
+struct WithWarning<T> {
+    #[serde(flatten)]
+    body: T,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    warning: Option<String>,
+}
+
+// 204 没有响应体，警告放在 Warning 头中
+fn no_content(warning: Option<String>) -> HttpResponse {
+    let mut response = HttpResponse::NoContent();
+    if let Some(warning) = warning {
+        let text = warning.replace(|c: char| c.is_control() || c == '"', " ");
+        if let Ok(value) =
+            header::HeaderValue::from_bytes(format!("199 git-demo \"{}\"", text).as_bytes())
+        {
+            response.insert_header((header::WARNING, value));
+        }
+    }
+    response.finish()
+}
+
+#[post("/repos/{name}/branches")]
+async fn create_branch(
+    name: web::Path<String>,
+    params: web::Json<CreateBranch>,
+    repo_manager: Data<Arc<RepoManager>>,
+) -> Result<HttpResponse, Error> {
+    let repo_name = RepoPath::parse(&name)?;
+    let repo = repo_manager.get_repo(&repo_name.bare_name())?;
+    let request = params.into_inner();
+    // 写入引用和同步工作仓库都是阻塞操作
+    let (branch, warning) = web::block(move || {
+        let branch = ref_service::create_branch(&repo, &request)?;
+        let target = git2::Oid::from_str(&branch.target).ok();
+        let warning = after_ref_change(&repo_name, &format!("refs/heads/{}", branch.name), target);
+        Ok::<_, RefError>((branch, warning))
+    })
+    .await??;
+    Ok(HttpResponse::Created().json(WithWarning {
+        body: branch,
+        warning,
+    }))
+}
+
+#[delete("/repos/{name}/branches/{branch:.*}")]
+async fn delete_branch(
+    path: web::Path<(String, String)>,
+    repo_manager: Data<Arc<RepoManager>>,
+) -> Result<HttpResponse, Error> {
+    let (name, branch) = path.into_inner();
+    let repo_name = RepoPath::parse(&name)?;
+    let repo = repo_manager.get_repo(&repo_name.bare_name())?;
+    let warning = web::block(move || {
+        ref_service::delete_branch(&repo, &branch)?;
+        Ok::<_, RefError>(after_ref_change(
+            &repo_name,
+            &format!("refs/heads/{}", branch),
+            None,
+        ))
+    })
+    .await??;
+    Ok(no_content(warning))
+}
+
+/// 创建标签，附注标签的标签者为认证时的用户名
+#[post("/repos/{name}/tags")]
+async fn create_tag(
+    name: web::Path<String>,
+    params: web::Json<CreateTag>,
+    user: AuthUser,
+    repo_manager: Data<Arc<RepoManager>>,
+) -> Result<HttpResponse, Error> {
+    let repo_name = RepoPath::parse(&name)?;
+    let repo = repo_manager.get_repo(&repo_name.bare_name())?;
+    let request = params.into_inner();
+    let (tag, warning) = web::block(move || {
+        let tagger = user
+            .signature()
+            .map_err(|e| RefError::Invalid(format!("用户名不能作为标签者: {}", e)))?;
+        let tag = ref_service::create_tag(&repo, &request, &tagger)?;
+        let target = git2::Oid::from_str(&tag.target).ok();
+        let warning = after_ref_change(&repo_name, &format!("refs/tags/{}", tag.name), target);
+        Ok::<_, RefError>((tag, warning))
+    })
+    .await??;
+    Ok(HttpResponse::Created().json(WithWarning { body: tag, warning }))
+}
+
+#[delete("/repos/{name}/tags/{tag:.*}")]
+async fn delete_tag(
+    path: web::Path<(String, String)>,
+    repo_manager: Data<Arc<RepoManager>>,
+) -> Result<HttpResponse, Error> {
+    let (name, tag) = path.into_inner();
+    let repo_name = RepoPath::parse(&name)?;
+    let repo = repo_manager.get_repo(&repo_name.bare_name())?;
+    let warning = web::block(move || {
+        ref_service::delete_tag(&repo, &tag)?;
+        Ok::<_, RefError>(after_ref_change(
+            &repo_name,
+            &format!("refs/tags/{}", tag),
+            None,
+        ))
+    })
+    .await??;
+    Ok(no_content(warning))
+}
+
+// 通过接口提交时请求体（全部文件内容）的上限
+const MAX_COMMIT_BODY: usize = 100 * 1024 * 1024;
+
+/// multipart 提交中的文本字段
+///
+/// 其余带文件名的部分是要上传的文件，文件名即仓库内路径（不存在时新建，存在时覆盖），
+/// 可重复的 `delete` 字段是要删除的路径。
+#[derive(Deserialize, Serialize)]
+pub struct FormParams {
+    pub branch_name: String,
+    pub commit_message: String,
+    pub expected_parent: Option<String>,
+}
+
+fn body_too_large() -> Error {
+    actix_web::error::ErrorPayloadTooLarge(format!("请求体不能超过 {} 字节", MAX_COMMIT_BODY))
+}
+
+async fn read_json_commit(mut payload: web::Payload) -> Result<NewCommit, Error> {
+    let mut body = web::BytesMut::new();
+    while let Some(chunk) = payload.next().await {
+        let chunk = chunk?;
+        if body.len() + chunk.len() > MAX_COMMIT_BODY {
+            return Err(body_too_large());
+        }
+        body.extend_from_slice(&chunk);
+    }
+    let request: CommitRequest = serde_json::from_slice(&body)
+        .map_err(|e| CommitError::Invalid(format!("请求体不是合法的 JSON: {}", e)))?;
+    Ok(NewCommit {
+        branch: request.branch,
+        message: request.message,
+        expected_parent: request.expected_parent,
+        changes: request
+            .actions
+            .into_iter()
+            .map(FileAction::into_change)
+            .collect::<Result<_, _>>()?,
+    })
+}
+
+async fn read_multipart_commit(mut multipart: Multipart) -> Result<NewCommit, Error> {
+    let mut fields = serde_json::Map::new();
+    let mut changes = Vec::new();
+    let mut total = 0;
+    while let Some(field) = multipart.next().await {
+        let mut field = field?;
+        let name = field.name().unwrap_or_default().to_string();
+        let filename = field
+            .content_disposition()
+            .and_then(|disposition| disposition.get_filename())
+            .map(str::to_string);
+        let mut data = Vec::new();
+        while let Some(chunk) = field.next().await {
+            let chunk = chunk?;
+            total += chunk.len();
+            if total > MAX_COMMIT_BODY {
+                return Err(body_too_large());
+            }
+            data.extend_from_slice(&chunk);
+        }
+        let change = |action, path| FileChange {
+            action,
+            path,
+            previous_path: None,
+            content: None,
+            executable: None,
+        };
+        match filename {
+            Some(path) => changes.push(FileChange {
+                content: Some(data),
+                ..change(ActionKind::Upsert, path)
+            }),
+            None if name == "delete" => changes.push(change(
+                ActionKind::Delete,
+                String::from_utf8_lossy(&data).trim().to_string(),
+            )),
+            None => {
+                let value = String::from_utf8(data)
+                    .map_err(|_| CommitError::Invalid(format!("字段 {} 不是 UTF-8 文本", name)))?;
+                fields.insert(name, value.into());
+            }
+        }
+    }
+    let params: FormParams = serde_json::from_value(fields.into())
+        .map_err(|e| CommitError::Invalid(format!("表单字段不完整: {}", e)))?;
+    Ok(NewCommit {
+        branch: params.branch_name,
+        message: params.commit_message,
+        expected_parent: params.expected_parent.filter(|sha| !sha.is_empty()),
+        changes,
+    })
+}
+
+/// 直接在裸仓库的分支上提交文件，作者为认证时的用户名
+///
+/// 请求体为 JSON（create、update、upsert、delete、move 操作列表）或 multipart 文件上传，
+/// 带 `expected_parent` 时分支必须仍指向该提交，否则返回 409。
+#[post("/repos/{name}/commits")]
+async fn create_commit(
+    req: HttpRequest,
+    name: web::Path<String>,
+    payload: web::Payload,
+    user: AuthUser,
+    repo_manager: Data<Arc<RepoManager>>,
+) -> Result<HttpResponse, Error> {
+    let repo_name = RepoPath::parse(&name)?;
+    let repo = repo_manager.get_repo(&repo_name.bare_name())?;
+    let multipart = req
+        .headers()
+        .get(header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|value| value.starts_with("multipart/form-data"));
+    let request = if multipart {
+        read_multipart_commit(Multipart::new(req.headers(), payload)).await?
+    } else {
+        read_json_commit(payload).await?
+    };
+    let incoming = request
+        .changes
+        .iter()
+        .filter_map(|change| change.content.as_ref())
+        .map(|content| content.len() as u64)
+        .sum();
+    quota_service::check_quota(&repo_name, incoming)?;
+
+    let refname = format!("refs/heads/{}", request.branch);
+    let (oid, commit) = web::block(move || {
+        let author = user
+            .signature()
+            .map_err(|e| CommitError::Invalid(format!("用户名不能作为作者: {}", e)))?;
+        let commit = commit_service::commit_changes(&repo, &request, &author)?;
+        Ok::<_, CommitError>((
+            commit.id(),
+            CommitDetail::from_commit(&repo, &commit, false),
+        ))
+    })
+    .await??;
+    let warning = after_ref_change(&repo_name, &refname, Some(oid));
+    Ok(HttpResponse::Created().json(WithWarning {
+        body: commit,
+        warning,
+    }))
+}
+
+#[derive(Debug, Serialize)]
+struct MirrorInfo {
+    config: MirrorConfig,
+    status: Option<MirrorStatus>,
+}
+
+#[get("/mirrors")]
+async fn list_mirrors(scheduler: Data<Arc<MirrorScheduler>>) -> HttpResponse {
+    HttpResponse::Ok().json(scheduler.statuses())
+}
+
+#[get("/repos/{name}/mirror")]
+async fn get_mirror(
+    name: web::Path<String>,
+    repo_manager: Data<Arc<RepoManager>>,
+    scheduler: Data<Arc<MirrorScheduler>>,
+) -> Result<HttpResponse, Error> {
+    let repo_name = RepoPath::parse(&name)?;
+    let repo = repo_manager.get_repo(&repo_name.bare_name())?;
+    let config = mirror_service::read_mirror_config(&repo)
+        .map_err(actix_web::error::ErrorInternalServerError)?;
+    Ok(HttpResponse::Ok().json(MirrorInfo {
+        config,
+        status: scheduler.status(&repo_name),
+    }))
+}
+
+#[put("/repos/{name}/mirror")]
+async fn put_mirror(
+    name: web::Path<String>,
+    params: web::Json<MirrorConfig>,
+    repo_manager: Data<Arc<RepoManager>>,
+    scheduler: Data<Arc<MirrorScheduler>>,
+) -> Result<HttpResponse, Error> {
+    let repo_name = RepoPath::parse(&name)?;
+    let repo = repo_manager.get_repo(&repo_name.bare_name())?;
+    let config = params.into_inner();
+    if let Err(e) = config.validate() {
+        return Ok(HttpResponse::BadRequest().body(format!("镜像登记非法: {}", e)));
+    }
+    mirror_service::write_mirror_config(&repo, &config)
+        .map_err(|e| actix_web::error::ErrorInternalServerError(e.to_string()))?;
+    scheduler.registration_changed(&repo_name, &config);
+    Ok(HttpResponse::Ok().json(MirrorInfo {
+        config,
+        status: scheduler.status(&repo_name),
+    }))
+}
+
+#[get("/repos/{name}/push-mirrors")]
+async fn list_push_mirrors(
+    name: web::Path<String>,
+    repo_manager: Data<Arc<RepoManager>>,
+) -> Result<HttpResponse, Error> {
+    let repo_name = RepoPath::parse(&name)?;
+    let repo = repo_manager.get_repo(&repo_name.bare_name())?;
+    Ok(HttpResponse::Ok().json(push_mirror_service::list_push_mirrors(&repo)?))
+}
+
+#[put("/repos/{name}/push-mirrors/{mirror}")]
+async fn put_push_mirror(
+    path: web::Path<(String, String)>,
+    params: web::Json<PushMirrorConfig>,
+    repo_manager: Data<Arc<RepoManager>>,
+) -> Result<HttpResponse, Error> {
+    let (name, mirror) = path.into_inner();
+    let repo_name = RepoPath::parse(&name)?;
+    let repo = repo_manager.get_repo(&repo_name.bare_name())?;
+    let info = push_mirror_service::put_push_mirror(&repo, &mirror, &params)?;
+    Ok(HttpResponse::Ok().json(info))
+}
+
+#[delete("/repos/{name}/push-mirrors/{mirror}")]
+async fn delete_push_mirror(
+    path: web::Path<(String, String)>,
+    repo_manager: Data<Arc<RepoManager>>,
+) -> Result<HttpResponse, Error> {
+    let (name, mirror) = path.into_inner();
+    let repo_name = RepoPath::parse(&name)?;
+    let repo = repo_manager.get_repo(&repo_name.bare_name())?;
+    push_mirror_service::delete_push_mirror(&repo, &mirror)?;
+    Ok(HttpResponse::NoContent().finish())
+}
+
+/// 立即推送到全部下游镜像，结果通过 GET /repos/{name}/push-mirrors 查看
+#[post("/repos/{name}/push-mirrors/push")]
+async fn push_to_mirrors(
+    name: web::Path<String>,
+    repo_manager: Data<Arc<RepoManager>>,
+) -> Result<HttpResponse, Error> {
+    let repo_name = RepoPath::parse(&name)?;
+    // 确认仓库存在
+    repo_manager.get_repo(&repo_name.bare_name())?;
+    push_mirror_service::schedule(&repo_name);
+    Ok(HttpResponse::Accepted().finish())
+}
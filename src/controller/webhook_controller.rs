@@ -0,0 +1,53 @@
+use crate::config::app_config::app_config;
+use crate::service::mirror_scheduler::MirrorScheduler;
+use crate::service::webhook_service::{self, Provider, WebhookError, WebhookResult};
+use actix_web::web::{Bytes, Data};
+use actix_web::{Error, HttpRequest, HttpResponse, post, web};
+use log::{info, warn};
+use std::sync::Arc;
+
+/// 接收上游平台的推送通知，立即同步对应的镜像
+#[post("/hooks/{provider}")]
+async fn receive_hook(
+    provider: web::Path<String>,
+    req: HttpRequest,
+    body: Bytes,
+    scheduler: Data<Arc<MirrorScheduler>>,
+) -> Result<HttpResponse, Error> {
+    let provider = Provider::parse(&provider)?;
+    if let Err(e) = provider.verify(req.headers(), &body) {
+        warn!("拒绝 {} webhook: {}", provider.name(), e);
+        return Err(e.into());
+    }
+
+    let event = provider.event(req.headers());
+    let mut result = WebhookResult {
+        provider: provider.name(),
+        event,
+        ignored: false,
+        queued: Vec::new(),
+    };
+    if !provider.is_ref_event(&result.event) {
+        // ping 等与引用无关的事件直接确认
+        info!("忽略 {} webhook 事件: {}", provider.name(), result.event);
+        result.ignored = true;
+        return Ok(HttpResponse::Ok().json(result));
+    }
+    if !app_config().mirror_config.enabled {
+        return Err(WebhookError::SchedulerDisabled.into());
+    }
+
+    let urls = webhook_service::repository_urls(&body)?;
+    let mirrors = web::block(move || webhook_service::matching_mirrors(&urls)).await?;
+    for (repo_name, mirror) in &mirrors {
+        scheduler.trigger(repo_name, mirror);
+        result.queued.push(repo_name.to_string());
+    }
+    info!(
+        "{} webhook {} 触发镜像同步: {:?}",
+        provider.name(),
+        result.event,
+        result.queued
+    );
+    Ok(HttpResponse::Accepted().json(result))
+}
@@ -7,6 +7,8 @@ pub mod logger;
 use crate::config::tls_config::load_rustls_config;
 // use crate::logger::SimpleLogger;
 use repo::barerepo_manager::RepoManager;
+use service::job_service::JobManager;
+use service::mirror_scheduler::MirrorScheduler;
 use std::sync::Arc;
 use actix_web::web;
 use actix_web::{App, HttpServer};
@@ -22,13 +24,20 @@ async fn main() -> std::io::Result<()> {
     // 初始化仓库管理器
     let repo_manager = Arc::new(RepoManager::new("bare_repos"));
 
+    // 启动镜像后台同步
+    let mirror_scheduler = MirrorScheduler::new();
+    mirror_scheduler.start();
+
+    // 克隆、拉取等耗时操作的后台任务队列
+    let job_manager = JobManager::new();
+
     // 加载TLS配置
     let tls_config = load_rustls_config(
         "/etc/letsencrypt/live/git-demo.dy-sec.com/fullchain.pem", 
         "/etc/letsencrypt/live/git-demo.dy-sec.com/privkey.pem"
     ).map_err(|e| {
         log::error!("Failed to load TLS config: {}", e);
-        std::io::Error::new(std::io::ErrorKind::Other, "TLS config error")
+        std::io::Error::other("TLS config error")
     })?;
 
     // 创建App工厂
@@ -37,6 +46,8 @@ async fn main() -> std::io::Result<()> {
            .wrap(logger::SimpleLogger) 
             .wrap(auth::token_auth::TokenAuthMiddleware)
             .app_data(web::Data::new(repo_manager.clone()))
+            .app_data(web::Data::new(mirror_scheduler.clone()))
+            .app_data(web::Data::new(job_manager.clone()))
             .route("/", web::get().to(|| async { "Git Server Running" }))
             .configure(controller::git_controller::path_config)
     };
@@ -1,6 +1,10 @@
+use crate::repo::pkt_line::pkt_line;
+use crate::repo::repo_path::{RepoPath, RepoPathError};
 use actix_web::Error;
-use git2::Repository;
-use log::info;
+use git2::{BranchType, Reference, Repository};
+use log::{info, warn};
+use std::fs::File;
+use std::io::{Seek, SeekFrom, Write};
 use std::path::{Path, PathBuf};
 use std::process::Command;
 pub struct RepoManager {
@@ -17,14 +21,14 @@ impl RepoManager {
         }
     }
 
-    // 获取裸仓库的完整路径
-    pub fn get_bare_repo_path(&self, repo_name: &str) -> PathBuf {
+    // 获取裸仓库的完整路径，仓库名会先经过 RepoPath 校验
+    pub fn get_bare_repo_path(&self, repo_name: &str) -> Result<PathBuf, RepoPathError> {
         info!("当前的裸仓库repo_name:{}", repo_name);
-        self.base_path.join(repo_name)
+        RepoPath::parse(repo_name)?.bare_dir(&self.base_path)
     }
 
     pub fn get_repo(&self, repo_name: &str) -> Result<Repository, actix_web::Error> {
-        let repo_path = self.get_bare_repo_path(repo_name);
+        let repo_path = self.get_bare_repo_path(repo_name)?;
         info!("当前的裸仓库的名称为：{:?}", repo_path);
         if !repo_path.exists() {
             return Err(actix_web::error::ErrorNotFound(format!(
@@ -54,9 +58,26 @@ impl RepoManager {
             .head()
             .map_err(|e| actix_web::error::ErrorInternalServerError(e.to_string()))?;
 
+        // HEAD 指向的分支通过 symref 能力告知客户端，clone 时据此选择默认分支
+        let symref = repo
+            .find_reference("HEAD")
+            .ok()
+            .and_then(|r| r.symbolic_target().map(|t| format!(" symref=HEAD:{}", t)))
+            .unwrap_or_default();
+        // 按仓库配置通告部分克隆相关能力，与 git upload-pack 的判断保持一致
+        let config_enabled =
+            |key: &str| matches!(repo.config().and_then(|c| c.get_bool(key)), Ok(true));
+        let mut extra_caps = String::new();
+        if config_enabled("uploadpack.allowFilter") {
+            extra_caps.push_str(" filter");
+        }
+        if config_enabled("uploadpack.allowReachableSHA1InWant") {
+            extra_caps.push_str(" allow-reachable-sha1-in-want");
+        }
+
         // 检查HEAD是否有有效的目标
         if let Some(head_oid) = head.target() {
-            refs.push(format!("{} HEAD\0multi_ack multi_ack_detailed thin-pack side-band side-band-64k ofs-delta shallow deepen-since deepen-not deepen-relative no-progress include-tag ofs-delta agent=git/2.40.0\n", head_oid));
+            refs.push(format!("{} HEAD\0multi_ack multi_ack_detailed thin-pack side-band side-band-64k ofs-delta shallow deepen-since deepen-not deepen-relative no-progress include-tag ofs-delta{}{} agent=git/2.40.0\n", head_oid, symref, extra_caps));
         } else {
             // HEAD没有指向有效的提交，返回错误
             return Err(actix_web::error::ErrorInternalServerError(
@@ -91,11 +112,57 @@ impl RepoManager {
         Ok(buf)
     }
 
+    // 获取裸仓库的默认分支，即 HEAD 这个 symref 指向的分支名
+    pub fn get_default_branch(&self, repo_name: &str) -> Result<String, Error> {
+        let repo = self.get_repo(repo_name)?;
+        let head = repo
+            .find_reference("HEAD")
+            .map_err(|e| actix_web::error::ErrorInternalServerError(e.to_string()))?;
+        let target = head.symbolic_target().ok_or_else(|| {
+            actix_web::error::ErrorInternalServerError("HEAD is detached, not a symref")
+        })?;
+        Ok(target
+            .strip_prefix("refs/heads/")
+            .unwrap_or(target)
+            .to_string())
+    }
+
+    // 修改裸仓库的默认分支，目标分支必须已存在
+    pub fn set_default_branch(&self, repo_name: &str, branch: &str) -> Result<(), Error> {
+        let refname = format!("refs/heads/{}", branch);
+        if !Reference::is_valid_name(&refname) {
+            return Err(actix_web::error::ErrorBadRequest(format!(
+                "Invalid branch name: {}",
+                branch
+            )));
+        }
+
+        let repo = self.get_repo(repo_name)?;
+        if repo.find_branch(branch, BranchType::Local).is_err() {
+            return Err(actix_web::error::ErrorNotFound(format!(
+                "Branch {} not found",
+                branch
+            )));
+        }
+
+        repo.set_head(&refname)
+            .map_err(|e| actix_web::error::ErrorInternalServerError(e.to_string()))?;
+        info!("裸仓库 {} 的默认分支已切换为 {}", repo_name, branch);
+        Ok(())
+    }
+
     // 检查裸仓库是否存在
     pub fn repo_exists(&self, repo_name: &str) -> bool {
-        let path = self.get_bare_repo_path(repo_name);
-        println!("Checking repo at: {:?}", path); // 添加调试日志
-        path.exists()
+        match self.get_bare_repo_path(repo_name) {
+            Ok(path) => {
+                info!("Checking repo at: {:?}", path);
+                path.exists()
+            }
+            Err(e) => {
+                warn!("非法的仓库名 {}: {}", repo_name, e);
+                false
+            }
+        }
     }
 
     // 处理 git-upload-pack 请求
@@ -103,35 +170,71 @@ impl RepoManager {
     // git2::Repository 并未直接提供名为'upload_pack'的方法来处理通过任意流进行的 Git 协议。
     // 在 Rust 中实现 git-upload-pack 服务的一种常见方法是将 git-upload-pack 可执行文件作为子进程执行，并通过管道来传递输入和输出。
     pub fn handle_upload_pack(&self, repo_name: &str, input: &[u8]) -> Result<Vec<u8>, Error> {
-        let repo_path = self.get_bare_repo_path(repo_name);
+        let repo_path = self.get_bare_repo_path(repo_name)?;
 
         // 创建临时文件存储输入数据
-        let temp_dir = tempfile::tempdir()?;
-        let input_path = temp_dir.path().join("upload_pack_input");
-        std::fs::write(&input_path, input)?;
+        let mut input_file = tempfile::tempfile()?;
+        input_file.write_all(input)?;
+        input_file.seek(SeekFrom::Start(0))?;
 
         // 执行git-upload-pack
+        let mut result = run_stateless_rpc("upload-pack", &repo_path, input_file)?;
+
+        // 确保输出以"0000"结尾
+        if !result.ends_with(b"0000") {
+            result.extend(b"0000");
+        }
+
+        Ok(result)
+    }
+
+    // 获取 git-receive-pack 的引用通告（用于 info/refs?service=git-receive-pack）
+    pub fn get_receive_refs(&self, repo_name: &str) -> Result<Vec<u8>, Error> {
+        let repo_path = self.get_bare_repo_path(repo_name)?;
         let output = Command::new("git")
-            .arg("upload-pack")
+            .arg("receive-pack")
             .arg("--stateless-rpc")
+            .arg("--advertise-refs")
             .arg(&repo_path)
-            .stdin(std::fs::File::open(&input_path)?)
             .output()?;
 
         if !output.status.success() {
             let err_msg = String::from_utf8_lossy(&output.stderr);
             return Err(actix_web::error::ErrorInternalServerError(format!(
-                "git-upload-pack failed: {}",
+                "git-receive-pack failed: {}",
                 err_msg
             )));
         }
 
-        // 确保输出以"0000"结尾
-        let mut result = output.stdout;
-        if !result.ends_with(b"0000") {
-            result.extend(b"0000");
-        }
+        let mut buf = pkt_line(b"# service=git-receive-pack\n");
+        buf.extend(b"0000");
+        buf.extend(output.stdout);
+        Ok(buf)
+    }
 
-        Ok(result)
+    // 处理 git-receive-pack 请求（客户端推送），input 是已经落盘的请求体
+    pub fn handle_receive_pack(&self, repo_name: &str, input: File) -> Result<Vec<u8>, Error> {
+        let repo_path = self.get_bare_repo_path(repo_name)?;
+        run_stateless_rpc("receive-pack", &repo_path, input)
     }
 }
+
+// 以 --stateless-rpc 模式执行 git 服务子进程，input 作为标准输入
+fn run_stateless_rpc(service: &str, repo_path: &Path, input: File) -> Result<Vec<u8>, Error> {
+    let output = Command::new("git")
+        .arg(service)
+        .arg("--stateless-rpc")
+        .arg(repo_path)
+        .stdin(input)
+        .output()?;
+
+    if !output.status.success() {
+        let err_msg = String::from_utf8_lossy(&output.stderr);
+        return Err(actix_web::error::ErrorInternalServerError(format!(
+            "git-{} failed: {}",
+            service, err_msg
+        )));
+    }
+
+    Ok(output.stdout)
+}
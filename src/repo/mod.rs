@@ -1 +1,3 @@
-pub mod barerepo_manager;
+pub mod barerepo_manager;
+pub mod pkt_line;
+pub mod repo_path;
@@ -0,0 +1,317 @@
+// Git smart HTTP 协议中 pkt-line 的编解码
+// 格式：4 位十六进制长度（包含这 4 个字节）+ 数据，"0000" 为 flush-pkt
+
+use std::io::{self, Read};
+
+/// side-band 中单个数据包的最大负载（side-band-64k）
+const SIDEBAND_64K_MAX: usize = 65515;
+/// side-band（非 64k）中单个数据包的最大负载
+const SIDEBAND_MAX: usize = 995;
+
+/// 按 pkt-line 格式编码一行
+pub fn pkt_line(data: &[u8]) -> Vec<u8> {
+    let mut buf = format!("{:04x}", data.len() + 4).into_bytes();
+    buf.extend(data);
+    buf
+}
+
+/// 以 `ERR` 包的形式返回错误，客户端会显示为 `remote error: <msg>`
+pub fn err_line(msg: &str) -> Vec<u8> {
+    pkt_line(format!("ERR {}\n", msg).as_bytes())
+}
+
+/// receive-pack 请求中的一条引用更新命令：`<old> <new> <ref>`
+#[derive(Debug, Clone)]
+pub struct RefUpdate {
+    pub old: String,
+    pub new: String,
+    pub refname: String,
+}
+
+impl RefUpdate {
+    /// 新值为全 0 表示删除该引用
+    pub fn is_delete(&self) -> bool {
+        self.new.bytes().all(|b| b == b'0')
+    }
+
+    /// 旧值为全 0 表示新建该引用
+    pub fn is_create(&self) -> bool {
+        self.old.bytes().all(|b| b == b'0')
+    }
+}
+
+/// 解析 receive-pack 请求开头的命令列表（直到第一个 flush-pkt）
+/// 返回引用更新命令和客户端声明的能力
+pub fn parse_receive_commands(input: &[u8]) -> (Vec<RefUpdate>, Vec<String>) {
+    let mut updates = Vec::new();
+    let mut capabilities = Vec::new();
+    let mut pos = 0;
+
+    while pos + 4 <= input.len() {
+        let len = match std::str::from_utf8(&input[pos..pos + 4])
+            .ok()
+            .and_then(|s| usize::from_str_radix(s, 16).ok())
+        {
+            Some(len) => len,
+            None => break,
+        };
+        if len == 0 || len < 4 || pos + len > input.len() {
+            break;
+        }
+        let line = String::from_utf8_lossy(&input[pos + 4..pos + len]);
+        pos += len;
+
+        let line = line.trim_end_matches('\n');
+        let (command, caps) = match line.split_once('\0') {
+            Some((command, caps)) => (command, Some(caps)),
+            None => (line, None),
+        };
+        if let Some(caps) = caps {
+            capabilities.extend(caps.split(' ').filter(|c| !c.is_empty()).map(String::from));
+        }
+        let mut parts = command.splitn(3, ' ');
+        if let (Some(old), Some(new), Some(refname)) = (parts.next(), parts.next(), parts.next()) {
+            updates.push(RefUpdate {
+                old: old.to_string(),
+                new: new.to_string(),
+                refname: refname.to_string(),
+            });
+        }
+    }
+    (updates, capabilities)
+}
+
+/// 从 receive-pack 请求中读出命令部分（到第一个 flush-pkt 为止，含 flush-pkt）
+///
+/// 推送请求可能很大，先落盘后只需读取开头的命令部分即可判断要更新哪些引用。
+pub fn read_command_section<R: Read>(reader: &mut R) -> io::Result<Vec<u8>> {
+    let mut section = Vec::new();
+    loop {
+        let mut header = [0u8; 4];
+        match reader.read_exact(&mut header) {
+            Ok(()) => {}
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(section),
+            Err(e) => return Err(e),
+        }
+        section.extend(header);
+        let len = std::str::from_utf8(&header)
+            .ok()
+            .and_then(|s| usize::from_str_radix(s, 16).ok())
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "pkt-line 长度非法"))?;
+        if len == 0 {
+            return Ok(section);
+        }
+        if len < 4 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "pkt-line 长度非法",
+            ));
+        }
+        let start = section.len();
+        section.resize(start + len - 4, 0);
+        reader.read_exact(&mut section[start..])?;
+    }
+}
+
+/// 构造拒绝整个推送的 receive-pack 响应
+///
+/// 按 report-status 协议返回 `unpack <reason>` 和每个引用的 `ng <ref> <reason>`，
+/// 客户端协商了 side-band 时额外通过 2 号通道输出提示，使其显示为 `remote: ...`。
+pub fn reject_receive_pack(input: &[u8], reason: &str) -> Vec<u8> {
+    let (updates, capabilities) = parse_receive_commands(input);
+    let reason = reason.replace('\n', " ");
+
+    let mut report = pkt_line(format!("unpack {}\n", reason).as_bytes());
+    for update in &updates {
+        report.extend(pkt_line(
+            format!("ng {} {}\n", update.refname, reason).as_bytes(),
+        ));
+    }
+    report.extend(b"0000");
+
+    let has_cap = |cap: &str| capabilities.iter().any(|c| c == cap);
+    let max = if has_cap("side-band-64k") {
+        SIDEBAND_64K_MAX
+    } else if has_cap("side-band") {
+        SIDEBAND_MAX
+    } else {
+        return if has_cap("report-status") {
+            report
+        } else {
+            err_line(&reason)
+        };
+    };
+
+    let mut buf = Vec::new();
+    let mut message = vec![2u8];
+    message.extend(format!("{}\n", reason).as_bytes());
+    buf.extend(pkt_line(&message));
+    for chunk in report.chunks(max - 1) {
+        let mut data = vec![1u8];
+        data.extend(chunk);
+        buf.extend(pkt_line(&data));
+    }
+    buf.extend(b"0000");
+    buf
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const OLD: &str = "1111111111111111111111111111111111111111";
+    const NEW: &str = "2222222222222222222222222222222222222222";
+    const ZERO: &str = "0000000000000000000000000000000000000000";
+
+    // 构造一个推送请求：命令列表 + flush-pkt + pack 数据
+    fn push_request(commands: &[(&str, &str, &str)], caps: &str) -> Vec<u8> {
+        let mut buf = Vec::new();
+        for (i, (old, new, refname)) in commands.iter().enumerate() {
+            let line = if i == 0 {
+                format!("{} {} {}\0{}\n", old, new, refname, caps)
+            } else {
+                format!("{} {} {}\n", old, new, refname)
+            };
+            buf.extend(pkt_line(line.as_bytes()));
+        }
+        buf.extend(b"0000");
+        buf.extend(b"PACK\x00\x00\x00\x02 pack data");
+        buf
+    }
+
+    // 把响应拆成 pkt-line 负载，flush-pkt 记为 None
+    fn split_pkt_lines(mut input: &[u8]) -> Vec<Option<Vec<u8>>> {
+        let mut lines = Vec::new();
+        while input.len() >= 4 {
+            let len = usize::from_str_radix(std::str::from_utf8(&input[..4]).unwrap(), 16).unwrap();
+            if len == 0 {
+                lines.push(None);
+                input = &input[4..];
+            } else {
+                lines.push(Some(input[4..len].to_vec()));
+                input = &input[len..];
+            }
+        }
+        assert!(input.is_empty(), "响应末尾有残留数据");
+        lines
+    }
+
+    #[test]
+    fn encodes_pkt_lines() {
+        assert_eq!(pkt_line(b"a\n"), b"0006a\n");
+        assert_eq!(pkt_line(b""), b"0004");
+        assert_eq!(err_line("no"), b"000bERR no\n");
+    }
+
+    #[test]
+    fn parses_commands_and_capabilities() {
+        let input = push_request(
+            &[
+                (OLD, NEW, "refs/heads/main"),
+                (OLD, ZERO, "refs/heads/old"),
+                (ZERO, NEW, "refs/tags/v1"),
+            ],
+            "report-status side-band-64k agent=git/2.40",
+        );
+        let (updates, caps) = parse_receive_commands(&input);
+        let refs: Vec<_> = updates.iter().map(|u| u.refname.as_str()).collect();
+        assert_eq!(refs, ["refs/heads/main", "refs/heads/old", "refs/tags/v1"]);
+        assert_eq!(updates[0].old, OLD);
+        assert_eq!(updates[0].new, NEW);
+        assert!(!updates[0].is_delete() && !updates[0].is_create());
+        assert!(updates[1].is_delete());
+        assert!(!updates[2].is_delete() && updates[2].is_create());
+        assert_eq!(caps, ["report-status", "side-band-64k", "agent=git/2.40"]);
+    }
+
+    #[test]
+    fn parse_stops_at_malformed_input() {
+        assert_eq!(parse_receive_commands(b"").0.len(), 0);
+        assert_eq!(parse_receive_commands(b"zzzz").0.len(), 0);
+        assert_eq!(parse_receive_commands(b"0003").0.len(), 0);
+        // 声明的长度超过实际数据
+        assert_eq!(parse_receive_commands(b"00ffabc").0.len(), 0);
+        // 截断在第二条命令中间时保留第一条
+        let mut input = pkt_line(format!("{} {} refs/heads/main\n", OLD, NEW).as_bytes());
+        input.extend(b"0040short");
+        assert_eq!(parse_receive_commands(&input).0.len(), 1);
+    }
+
+    #[test]
+    fn reads_only_the_command_section() {
+        let input = push_request(
+            &[(OLD, NEW, "refs/heads/main"), (OLD, NEW, "refs/heads/b")],
+            "",
+        );
+        let mut reader = &input[..];
+        let section = read_command_section(&mut reader).unwrap();
+        assert!(section.ends_with(b"0000"));
+        assert_eq!(reader, b"PACK\x00\x00\x00\x02 pack data");
+        assert_eq!(parse_receive_commands(&section).0.len(), 2);
+
+        // 没有 flush-pkt 时读到结尾为止
+        let mut reader = &pkt_line(b"abc")[..];
+        assert_eq!(read_command_section(&mut reader).unwrap(), pkt_line(b"abc"));
+        assert!(read_command_section(&mut &b"00zz"[..]).is_err());
+        assert!(read_command_section(&mut &b"0002"[..]).is_err());
+        assert!(read_command_section(&mut &b"0010abc"[..]).is_err());
+    }
+
+    #[test]
+    fn rejects_with_report_status() {
+        let input = push_request(
+            &[(OLD, NEW, "refs/heads/main"), (OLD, ZERO, "refs/heads/dev")],
+            "report-status",
+        );
+        let lines = split_pkt_lines(&reject_receive_pack(&input, "超出配额\n请清理"));
+        assert_eq!(
+            lines,
+            [
+                Some("unpack 超出配额 请清理\n".as_bytes().to_vec()),
+                Some("ng refs/heads/main 超出配额 请清理\n".as_bytes().to_vec()),
+                Some("ng refs/heads/dev 超出配额 请清理\n".as_bytes().to_vec()),
+                None,
+            ]
+        );
+    }
+
+    #[test]
+    fn rejects_without_report_status_as_err() {
+        let input = push_request(&[(OLD, NEW, "refs/heads/main")], "");
+        assert_eq!(reject_receive_pack(&input, "full"), err_line("full"));
+    }
+
+    #[test]
+    fn rejects_over_side_band() {
+        for (cap, max) in [
+            ("side-band-64k", SIDEBAND_64K_MAX),
+            ("side-band", SIDEBAND_MAX),
+        ] {
+            let refs: Vec<String> = (0..40)
+                .map(|i| format!("refs/heads/branch-{:02}", i))
+                .collect();
+            let commands: Vec<_> = refs.iter().map(|r| (OLD, NEW, r.as_str())).collect();
+            let input = push_request(&commands, &format!("report-status {}", cap));
+            let lines = split_pkt_lines(&reject_receive_pack(&input, "full"));
+
+            assert_eq!(lines.last(), Some(&None));
+            assert_eq!(lines[0].as_deref(), Some(&b"\x02full\n"[..]));
+            // 1 号通道中的数据拼起来就是 report-status 报告
+            let mut report = Vec::new();
+            for line in &lines[1..lines.len() - 1] {
+                let line = line.as_ref().unwrap();
+                assert_eq!(line[0], 1);
+                assert!(line.len() <= max);
+                report.extend(&line[1..]);
+            }
+            let report = split_pkt_lines(&report);
+            assert_eq!(report.len(), refs.len() + 2);
+            assert_eq!(report[0].as_deref(), Some(&b"unpack full\n"[..]));
+            assert_eq!(
+                report[1].as_deref(),
+                Some(&b"ng refs/heads/branch-00 full\n"[..])
+            );
+            assert_eq!(report.last(), Some(&None));
+        }
+    }
+}
@@ -0,0 +1,372 @@
+use actix_web::{HttpResponse, ResponseError};
+use std::fmt;
+use std::path::{Component, Path, PathBuf};
+use thiserror::Error;
+
+/// 仓库名总长度上限（含命名空间）
+const MAX_NAME_LEN: usize = 128;
+/// 单个路径段长度上限
+const MAX_SEGMENT_LEN: usize = 64;
+/// 命名空间最多一层，即 `namespace/name`
+const MAX_SEGMENTS: usize = 2;
+
+/// 不允许作为仓库名的保留字（不区分大小写）
+/// 包含 Git 内部目录名以及 Windows 设备名
+const RESERVED_NAMES: &[&str] = &[
+    "head",
+    "config",
+    "description",
+    "info",
+    "objects",
+    "refs",
+    "hooks",
+    "packed-refs",
+    "logs",
+    "con",
+    "prn",
+    "aux",
+    "nul",
+    "com1",
+    "com2",
+    "com3",
+    "com4",
+    "com5",
+    "com6",
+    "com7",
+    "com8",
+    "com9",
+    "lpt1",
+    "lpt2",
+    "lpt3",
+    "lpt4",
+    "lpt5",
+    "lpt6",
+    "lpt7",
+    "lpt8",
+    "lpt9",
+];
+
+#[derive(Debug, Error, PartialEq, Eq)]
+pub enum RepoPathError {
+    #[error("仓库名不能为空")]
+    Empty,
+    #[error("仓库名过长: {0} 字节，最多 {MAX_NAME_LEN} 字节")]
+    TooLong(usize),
+    #[error("仓库名层级过深，最多允许 `命名空间/仓库名` 两层")]
+    TooManySegments,
+    #[error("仓库名片段非法: {0:?}")]
+    InvalidSegment(String),
+    #[error("仓库名包含非法字符: {0:?}")]
+    InvalidChar(char),
+    #[error("仓库名是保留字: {0}")]
+    Reserved(String),
+    #[error("路径越界: {0}")]
+    Escape(String),
+    #[error("路径规范化失败: {0}")]
+    Io(String),
+}
+
+impl ResponseError for RepoPathError {
+    fn status_code(&self) -> actix_web::http::StatusCode {
+        match self {
+            RepoPathError::Io(_) => actix_web::http::StatusCode::INTERNAL_SERVER_ERROR,
+            _ => actix_web::http::StatusCode::BAD_REQUEST,
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        HttpResponse::build(self.status_code()).body(self.to_string())
+    }
+}
+
+/// 经过校验的仓库名
+///
+/// 所有把用户输入拼接到 `test_repos/`、`bare_repos/` 下的地方都必须先解析为 `RepoPath`，
+/// 再通过 [`RepoPath::work_dir`] / [`RepoPath::bare_dir`] 得到规范化后的路径。
+/// 末尾的 `.git` 会被去掉，`zss` 和 `zss.git` 指向同一个仓库。
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct RepoPath {
+    name: String,
+}
+
+impl RepoPath {
+    pub fn parse(raw: &str) -> Result<Self, RepoPathError> {
+        let trimmed = raw.trim_matches('/');
+        if trimmed.is_empty() {
+            return Err(RepoPathError::Empty);
+        }
+        if trimmed.len() > MAX_NAME_LEN {
+            return Err(RepoPathError::TooLong(trimmed.len()));
+        }
+
+        let name = trimmed.strip_suffix(".git").unwrap_or(trimmed);
+        let segments: Vec<&str> = name.split('/').collect();
+        if segments.len() > MAX_SEGMENTS {
+            return Err(RepoPathError::TooManySegments);
+        }
+        for segment in &segments {
+            validate_segment(segment)?;
+        }
+
+        Ok(Self {
+            name: name.to_string(),
+        })
+    }
+
+    /// 规范化后的仓库名（不含 `.git` 后缀）
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// 命名空间（`namespace/name` 中的 `namespace`），没有则为 `None`
+    pub fn namespace(&self) -> Option<&str> {
+        self.name.split_once('/').map(|(ns, _)| ns)
+    }
+
+    /// 裸仓库目录名，例如 `zss.git`
+    pub fn bare_name(&self) -> String {
+        format!("{}.git", self.name)
+    }
+
+    /// 工作仓库路径：`<base>/<name>`
+    pub fn work_dir(&self, base: &Path) -> Result<PathBuf, RepoPathError> {
+        resolve_under(base, Path::new(&self.name))
+    }
+
+    /// 裸仓库路径：`<base>/<name>.git`
+    pub fn bare_dir(&self, base: &Path) -> Result<PathBuf, RepoPathError> {
+        resolve_under(base, Path::new(&self.bare_name()))
+    }
+}
+
+impl fmt::Display for RepoPath {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.name)
+    }
+}
+
+fn validate_segment(segment: &str) -> Result<(), RepoPathError> {
+    if segment.is_empty() || segment.len() > MAX_SEGMENT_LEN {
+        return Err(RepoPathError::InvalidSegment(segment.to_string()));
+    }
+    if let Some(c) = segment
+        .chars()
+        .find(|c| !(c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.')))
+    {
+        return Err(RepoPathError::InvalidChar(c));
+    }
+    // 必须以字母或数字开头，排除 `.`、`..`、隐藏目录以及形如 `-x` 的命令行参数
+    if !segment.starts_with(|c: char| c.is_ascii_alphanumeric()) {
+        return Err(RepoPathError::InvalidSegment(segment.to_string()));
+    }
+    if segment.ends_with('.') || segment.ends_with(".lock") || segment.contains("..") {
+        return Err(RepoPathError::InvalidSegment(segment.to_string()));
+    }
+    let stem = segment.split('.').next().unwrap_or(segment);
+    if RESERVED_NAMES
+        .iter()
+        .any(|r| r.eq_ignore_ascii_case(segment) || r.eq_ignore_ascii_case(stem))
+    {
+        return Err(RepoPathError::Reserved(segment.to_string()));
+    }
+    Ok(())
+}
+
+/// 在 `base` 下解析相对路径 `rel`，并保证结果不会逃出 `base`
+///
+/// 只做校验，不会创建任何目录。目标（或其最近的已存在祖先）会被 canonicalize，
+/// 因此指向外部的符号链接同样会被拒绝。
+pub fn resolve_under(base: &Path, rel: &Path) -> Result<PathBuf, RepoPathError> {
+    if rel.components().any(|c| !matches!(c, Component::Normal(_))) {
+        return Err(RepoPathError::Escape(rel.display().to_string()));
+    }
+
+    let base = canonicalize_existing(base)?;
+    let resolved = canonicalize_existing(&base.join(rel))?;
+    if !resolved.starts_with(&base) {
+        return Err(RepoPathError::Escape(rel.display().to_string()));
+    }
+    Ok(resolved)
+}
+
+// canonicalize 最近的已存在祖先，再拼回尚不存在的部分
+fn canonicalize_existing(path: &Path) -> Result<PathBuf, RepoPathError> {
+    let path = std::path::absolute(path).map_err(|e| RepoPathError::Io(e.to_string()))?;
+    let mut existing = path.as_path();
+    while existing.symlink_metadata().is_err() {
+        existing = match existing.parent() {
+            Some(parent) => parent,
+            None => break,
+        };
+    }
+    let canonical = existing
+        .canonicalize()
+        .map_err(|e| RepoPathError::Io(e.to_string()))?;
+    let rest = path
+        .strip_prefix(existing)
+        .map_err(|e| RepoPathError::Io(e.to_string()))?;
+    Ok(canonical.join(rest))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::prelude::*;
+    use std::os::unix::fs::symlink;
+
+    // 容易造成路径穿越的片段
+    fn hostile_piece() -> impl Strategy<Value = String> {
+        prop_oneof![
+            Just("..".to_string()),
+            Just(".".to_string()),
+            Just("/".to_string()),
+            Just("\\".to_string()),
+            Just("\0".to_string()),
+            Just("%2e%2e".to_string()),
+            Just("%2f".to_string()),
+            Just("%5c".to_string()),
+            Just(".git".to_string()),
+            Just("~".to_string()),
+            Just(":".to_string()),
+            "[a-z0-9]{1,6}",
+        ]
+    }
+
+    fn hostile_name() -> impl Strategy<Value = String> {
+        prop::collection::vec(hostile_piece(), 1..8).prop_map(|pieces| pieces.concat())
+    }
+
+    fn assert_inside(base: &Path, name: &RepoPath) {
+        let base = base.canonicalize().unwrap();
+        for dir in [name.work_dir(&base), name.bare_dir(&base)] {
+            let dir = dir.unwrap();
+            assert!(dir.starts_with(&base), "{:?} 逃出了 {:?}", dir, base);
+            assert!(
+                dir.components()
+                    .all(|c| !matches!(c, Component::ParentDir | Component::CurDir)),
+                "{:?} 含有 `.` 或 `..`",
+                dir
+            );
+        }
+    }
+
+    proptest! {
+        #[test]
+        fn parsed_names_stay_under_base(raw in prop_oneof![hostile_name(), any::<String>()]) {
+            let base = tempfile::tempdir().unwrap();
+            if let Ok(name) = RepoPath::parse(&raw) {
+                prop_assert!(!name.name().contains(".."));
+                prop_assert!(!name.name().starts_with('/'));
+                prop_assert!(!name.name().contains(['\\', '\0', '%', ':', '~']));
+                assert_inside(base.path(), &name);
+            }
+        }
+
+        #[test]
+        fn absolute_and_parent_paths_are_rejected(raw in hostile_name()) {
+            let base = tempfile::tempdir().unwrap();
+            let absolute = format!("/{}", raw);
+            prop_assert!(resolve_under(base.path(), Path::new(&absolute)).is_err());
+            let parent = format!("../{}", raw);
+            prop_assert!(resolve_under(base.path(), Path::new(&parent)).is_err());
+            let nested = format!("{}/../../{}", raw, raw);
+            prop_assert!(resolve_under(base.path(), Path::new(&nested)).is_err());
+        }
+
+        #[test]
+        fn resolved_paths_never_escape(raw in hostile_name()) {
+            let base = tempfile::tempdir().unwrap();
+            let canonical = base.path().canonicalize().unwrap();
+            if let Ok(path) = resolve_under(base.path(), Path::new(&raw)) {
+                prop_assert!(path.starts_with(&canonical));
+            }
+        }
+    }
+
+    #[test]
+    fn rejects_known_bad_names() {
+        for raw in [
+            "",
+            "/",
+            "..",
+            "../etc",
+            "a/../../etc",
+            "a\\..\\b",
+            "a\0b",
+            "%2e%2e",
+            "..%2fetc",
+            ".git",
+            ".hidden",
+            "-upload-pack",
+            "a/b/c",
+            "HEAD",
+            "objects",
+            "con.git",
+            "x.lock",
+        ] {
+            assert!(RepoPath::parse(raw).is_err(), "{:?} 应被拒绝", raw);
+        }
+    }
+
+    #[test]
+    fn strips_git_suffix_and_slashes() {
+        let name = RepoPath::parse("/team/zss.git/").unwrap();
+        assert_eq!(name.name(), "team/zss");
+        assert_eq!(name.namespace(), Some("team"));
+        assert_eq!(name.bare_name(), "team/zss.git");
+        assert_eq!(
+            RepoPath::parse("zss").unwrap(),
+            RepoPath::parse("zss.git").unwrap()
+        );
+
+        // 开头的 `/` 会被去掉，绝对路径只会被当成 base 下的相对名字
+        let base = tempfile::tempdir().unwrap();
+        let name = RepoPath::parse("/etc/passwd").unwrap();
+        assert_inside(base.path(), &name);
+    }
+
+    #[test]
+    fn symlinks_out_of_base_are_rejected() {
+        let base = tempfile::tempdir().unwrap();
+        let outside = tempfile::tempdir().unwrap();
+        symlink(outside.path(), base.path().join("evil")).unwrap();
+        symlink(outside.path(), base.path().join("evil.git")).unwrap();
+
+        let name = RepoPath::parse("evil").unwrap();
+        assert!(matches!(
+            name.work_dir(base.path()),
+            Err(RepoPathError::Escape(_))
+        ));
+        assert!(matches!(
+            name.bare_dir(base.path()),
+            Err(RepoPathError::Escape(_))
+        ));
+        let nested = RepoPath::parse("evil/repo").unwrap();
+        assert!(matches!(
+            nested.work_dir(base.path()),
+            Err(RepoPathError::Escape(_))
+        ));
+
+        // 指向 base 内部的符号链接可以使用
+        std::fs::create_dir(base.path().join("real")).unwrap();
+        symlink(base.path().join("real"), base.path().join("alias")).unwrap();
+        let alias = RepoPath::parse("alias").unwrap();
+        assert_eq!(
+            alias.work_dir(base.path()).unwrap(),
+            base.path().canonicalize().unwrap().join("real")
+        );
+    }
+
+    #[test]
+    fn does_not_create_missing_base() {
+        let root = tempfile::tempdir().unwrap();
+        let base = root.path().join("missing");
+        let name = RepoPath::parse("zss").unwrap();
+        let dir = name.bare_dir(&base).unwrap();
+        assert_eq!(
+            dir,
+            root.path().canonicalize().unwrap().join("missing/zss.git")
+        );
+        assert!(!base.exists());
+    }
+}
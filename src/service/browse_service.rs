@@ -0,0 +1,1332 @@
+use actix_web::{HttpResponse, ResponseError};
+use base64::Engine;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use chrono::{DateTime, FixedOffset};
+use git2::{Blob, Commit, ObjectType, Oid, Repository, Tree};
+use log::warn;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::path::Path;
+use std::process::{Command, Stdio};
+use thiserror::Error;
+
+// 判断二进制时检查的字节数，与 git 一致
+const BINARY_CHECK_LEN: usize = 8000;
+
+#[derive(Debug, Error)]
+pub enum BrowseError {
+    #[error("请求非法: {0}")]
+    Invalid(String),
+    #[error("{0}")]
+    NotFound(String),
+    #[error("读取仓库失败: {0}")]
+    Git(#[from] git2::Error),
+    #[error("执行 git 命令失败: {0}")]
+    Command(#[from] std::io::Error),
+}
+
+impl ResponseError for BrowseError {
+    fn status_code(&self) -> actix_web::http::StatusCode {
+        match self {
+            BrowseError::Invalid(_) => actix_web::http::StatusCode::BAD_REQUEST,
+            BrowseError::NotFound(_) => actix_web::http::StatusCode::NOT_FOUND,
+            BrowseError::Git(_) | BrowseError::Command(_) => {
+                actix_web::http::StatusCode::INTERNAL_SERVER_ERROR
+            }
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        HttpResponse::build(self.status_code()).body(self.to_string())
+    }
+}
+
+/// 从对象库中读出的文件，内容直接引用 libgit2 载入的对象，不再复制
+pub struct RawBlob<'r> {
+    pub blob: Blob<'r>,
+    // 符号链接的内容是链接目标
+    pub symlink: bool,
+}
+
+impl RawBlob<'_> {
+    pub fn content(&self) -> &[u8] {
+        self.blob.content()
+    }
+}
+
+/// 把分支、标签或提交 ID 解析为提交，无法解析时返回 None
+pub fn resolve_commit<'r>(repo: &'r Repository, rev: &str) -> Option<Commit<'r>> {
+    if rev.is_empty() || rev.starts_with('-') {
+        return None;
+    }
+    repo.revparse_single(rev)
+        .and_then(|object| object.peel_to_commit())
+        .ok()
+}
+
+/// 把 `{ref}/{path}` 拆成提交和仓库内路径
+///
+/// 分支名可以包含 `/`，所以依次尝试更长的前缀，取第一个能解析为提交的。
+pub fn split_ref_path<'r>(
+    repo: &'r Repository,
+    spec: &str,
+) -> Result<(Commit<'r>, String), BrowseError> {
+    let segments: Vec<&str> = spec.split('/').collect();
+    for end in 1..=segments.len() {
+        if let Some(commit) = resolve_commit(repo, &segments[..end].join("/")) {
+            return Ok((commit, segments[end..].join("/")));
+        }
+    }
+    Err(BrowseError::NotFound(format!(
+        "引用 {} 不存在",
+        segments[0]
+    )))
+}
+
+/// 读取指定提交中的文件
+pub fn read_blob<'r>(
+    repo: &'r Repository,
+    commit: &Commit,
+    path: &str,
+) -> Result<RawBlob<'r>, BrowseError> {
+    if path.is_empty() {
+        return Err(BrowseError::Invalid("缺少文件路径".to_string()));
+    }
+    let entry = commit
+        .tree()?
+        .get_path(Path::new(path))
+        .map_err(|_| BrowseError::NotFound(format!("文件 {} 不存在", path)))?;
+    match entry.kind() {
+        Some(ObjectType::Blob) => {}
+        Some(ObjectType::Tree) => {
+            return Err(BrowseError::Invalid(format!("{} 是目录", path)));
+        }
+        // 子模块指向其他仓库的提交，没有内容可读
+        _ => return Err(BrowseError::NotFound(format!("{} 是子模块", path))),
+    }
+    Ok(RawBlob {
+        blob: repo.find_blob(entry.id())?,
+        symlink: entry.filemode() == i32::from(git2::FileMode::Link),
+    })
+}
+
+/// 与 git 相同的二进制判断：开头一段内容中含有 NUL 字节
+pub fn is_binary(content: &[u8]) -> bool {
+    content[..content.len().min(BINARY_CHECK_LEN)].contains(&0)
+}
+
+/// 按扩展名推断内容类型，无法推断时按内容区分文本和二进制
+pub fn content_type(path: &str, content: &[u8]) -> String {
+    let ext = Path::new(path)
+        .extension()
+        .and_then(|ext| ext.to_str())
+        .unwrap_or("");
+    let mime = actix_files::file_extension_to_mime(ext);
+    match (mime.type_().as_str(), mime.essence_str()) {
+        ("text", _) => format!("{}; charset=utf-8", mime.essence_str()),
+        (_, "application/octet-stream") if !is_binary(content) => {
+            "text/plain; charset=utf-8".to_string()
+        }
+        _ => mime.to_string(),
+    }
+}
+
+// 查找每个条目的最后提交时最多检查的提交数，超过后未找到的条目留空
+const LAST_COMMIT_WALK_LIMIT: usize = 10_000;
+
+/// 提交的简要信息
+#[derive(Debug, Clone, Serialize)]
+pub struct CommitSummary {
+    pub id: String,
+    pub summary: String,
+    pub author_name: String,
+    pub author_email: String,
+    pub authored_at: DateTime<FixedOffset>,
+}
+
+impl CommitSummary {
+    pub fn from_commit(commit: &Commit) -> Self {
+        let author = commit.author();
+        CommitSummary {
+            id: commit.id().to_string(),
+            summary: commit.summary().unwrap_or_default().to_string(),
+            author_name: author.name().unwrap_or_default().to_string(),
+            author_email: author.email().unwrap_or_default().to_string(),
+            authored_at: git_time(author.when()),
+        }
+    }
+}
+
+/// 把 git 的时间（含时区）转换为 chrono 时间
+pub fn git_time(time: git2::Time) -> DateTime<FixedOffset> {
+    let offset = FixedOffset::east_opt(time.offset_minutes() * 60)
+        .unwrap_or_else(|| FixedOffset::east_opt(0).unwrap());
+    DateTime::from_timestamp(time.seconds(), 0)
+        .unwrap_or_default()
+        .with_timezone(&offset)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum EntryType {
+    Blob,
+    Tree,
+    Submodule,
+    Symlink,
+}
+
+/// 目录中的一个条目
+#[derive(Debug, Serialize)]
+pub struct TreeEntryInfo {
+    pub name: String,
+    pub path: String,
+    #[serde(rename = "type")]
+    pub kind: EntryType,
+    // 八进制文件模式，例如 "100644"
+    pub mode: String,
+    pub oid: String,
+    // 只有文件和符号链接有大小
+    pub size: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub last_commit: Option<CommitSummary>,
+}
+
+/// 某个版本下的目录列表
+#[derive(Debug, Serialize)]
+pub struct TreeListing {
+    pub commit: String,
+    pub path: String,
+    pub entries: Vec<TreeEntryInfo>,
+}
+
+// 找到提交中的目录，路径为空时是根目录
+fn find_dir<'r>(
+    repo: &'r Repository,
+    commit: &Commit<'r>,
+    path: &str,
+) -> Result<Option<Tree<'r>>, git2::Error> {
+    let root = commit.tree()?;
+    if path.is_empty() {
+        return Ok(Some(root));
+    }
+    match root.get_path(Path::new(path)) {
+        Ok(entry) if entry.kind() == Some(ObjectType::Tree) => {
+            Ok(Some(repo.find_tree(entry.id())?))
+        }
+        _ => Ok(None),
+    }
+}
+
+/// 列出指定版本中的目录，目录在前，同类按名称排序
+///
+/// `last_commit` 为 true 时沿历史查找每个条目最后一次被修改的提交。
+pub fn list_tree(
+    repo: &Repository,
+    commit: &Commit,
+    path: &str,
+    last_commit: bool,
+) -> Result<TreeListing, BrowseError> {
+    let path = path.trim_end_matches('/');
+    let tree = match find_dir(repo, commit, path)? {
+        Some(tree) => tree,
+        None if commit.tree()?.get_path(Path::new(path)).is_ok() => {
+            return Err(BrowseError::Invalid(format!("{} 不是目录", path)));
+        }
+        None => return Err(BrowseError::NotFound(format!("目录 {} 不存在", path))),
+    };
+
+    let odb = repo.odb()?;
+    let mut entries = Vec::new();
+    for entry in tree.iter() {
+        let name = entry.name().unwrap_or_default().to_string();
+        let mode = entry.filemode();
+        let kind = match entry.kind() {
+            Some(ObjectType::Tree) => EntryType::Tree,
+            Some(ObjectType::Commit) => EntryType::Submodule,
+            _ if mode == i32::from(git2::FileMode::Link) => EntryType::Symlink,
+            _ => EntryType::Blob,
+        };
+        let size = match kind {
+            EntryType::Blob | EntryType::Symlink => Some(odb.read_header(entry.id())?.0),
+            _ => None,
+        };
+        entries.push(TreeEntryInfo {
+            path: if path.is_empty() {
+                name.clone()
+            } else {
+                format!("{}/{}", path, name)
+            },
+            name,
+            kind,
+            mode: format!("{:06o}", mode),
+            oid: entry.id().to_string(),
+            size,
+            last_commit: None,
+        });
+    }
+    entries.sort_by(|a, b| {
+        (a.kind != EntryType::Tree)
+            .cmp(&(b.kind != EntryType::Tree))
+            .then_with(|| a.name.cmp(&b.name))
+    });
+
+    if last_commit {
+        fill_last_commits(repo, commit, path, &mut entries)?;
+    }
+    Ok(TreeListing {
+        commit: commit.id().to_string(),
+        path: path.to_string(),
+        entries,
+    })
+}
+
+// 从指定提交向前遍历，条目是当前版本且与所有父提交中的版本都不同的提交，
+// 就是它最后被修改的提交；合并提交只要与某个父提交一致就跳过，与 git log 的历史简化相同
+fn fill_last_commits(
+    repo: &Repository,
+    commit: &Commit,
+    path: &str,
+    entries: &mut [TreeEntryInfo],
+) -> Result<(), BrowseError> {
+    let mut pending: HashMap<String, usize> = entries
+        .iter()
+        .enumerate()
+        .map(|(index, entry)| (entry.name.clone(), index))
+        .collect();
+    let mut walk = repo.revwalk()?;
+    walk.set_sorting(git2::Sort::TIME)?;
+    walk.push(commit.id())?;
+
+    for oid in walk.take(LAST_COMMIT_WALK_LIMIT) {
+        let current = repo.find_commit(oid?)?;
+        let Some(dir) = find_dir(repo, &current, path)? else {
+            continue;
+        };
+        let parents = current
+            .parents()
+            .map(|parent| find_dir(repo, &parent, path))
+            .collect::<Result<Vec<_>, _>>()?;
+        // 整个目录与某个父提交相同，说明这个提交没有改动其中的条目
+        if parents.iter().any(|parent| {
+            parent
+                .as_ref()
+                .is_some_and(|parent| parent.id() == dir.id())
+        }) {
+            continue;
+        }
+        let summary = CommitSummary::from_commit(&current);
+        pending.retain(|name, index| {
+            let id = dir.get_name(name).map(|entry| entry.id());
+            // 只认引入当前版本的提交，合并时被丢弃的分支上的修改不算
+            let is_current = id.is_some_and(|id| id.to_string() == entries[*index].oid);
+            let changed = is_current
+                && parents.iter().all(|parent| {
+                    parent
+                        .as_ref()
+                        .and_then(|parent| parent.get_name(name))
+                        .map(|entry| entry.id())
+                        != id
+                });
+            if changed {
+                entries[*index].last_commit = Some(summary.clone());
+            }
+            !changed
+        });
+        if pending.is_empty() {
+            break;
+        }
+    }
+    Ok(())
+}
+
+// 提交列表每页的默认条数和上限
+const DEFAULT_PER_PAGE: usize = 30;
+const MAX_PER_PAGE: usize = 100;
+
+/// 作者或提交者
+#[derive(Debug, Serialize)]
+pub struct Person {
+    pub name: String,
+    pub email: String,
+    pub date: DateTime<FixedOffset>,
+}
+
+impl Person {
+    pub fn from_signature(signature: &git2::Signature) -> Self {
+        Person {
+            name: signature.name().unwrap_or_default().to_string(),
+            email: signature.email().unwrap_or_default().to_string(),
+            date: git_time(signature.when()),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SignatureStatus {
+    Unsigned,
+    // 签名有效
+    Good,
+    // 签名与内容不符
+    Bad,
+    // 有签名但没有验证：未请求验证，或服务端没有对应的公钥或验证工具
+    Unverified,
+}
+
+/// 提交的签名状态，验证通过 `git verify-commit` 完成，使用服务端的 gpg/ssh 配置
+#[derive(Debug, Serialize)]
+pub struct SignatureInfo {
+    pub status: SignatureStatus,
+    // gpg、ssh 或 x509
+    pub format: Option<&'static str>,
+}
+
+/// 提交的完整信息
+#[derive(Debug, Serialize)]
+pub struct CommitDetail {
+    pub id: String,
+    pub parents: Vec<String>,
+    pub author: Person,
+    pub committer: Person,
+    pub message: String,
+    pub signature: SignatureInfo,
+    // 按路径过滤时该提交中被跟踪的路径，跟随重命名后可能与请求的路径不同
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub path: Option<String>,
+}
+
+impl CommitDetail {
+    /// `verify` 为 true 时调用 `git verify-commit` 验证签名，否则只识别签名格式
+    pub fn from_commit(repo: &Repository, commit: &Commit, verify: bool) -> Self {
+        CommitDetail {
+            id: commit.id().to_string(),
+            parents: commit.parent_ids().map(|id| id.to_string()).collect(),
+            author: Person::from_signature(&commit.author()),
+            committer: Person::from_signature(&commit.committer()),
+            message: String::from_utf8_lossy(commit.message_bytes()).into_owned(),
+            signature: signature_info(repo, commit.id(), verify),
+            path: None,
+        }
+    }
+}
+
+fn signature_info(repo: &Repository, oid: Oid, verify: bool) -> SignatureInfo {
+    let Ok((signature, _)) = repo.extract_signature(&oid, None) else {
+        return SignatureInfo {
+            status: SignatureStatus::Unsigned,
+            format: None,
+        };
+    };
+    let format = match &signature[..] {
+        s if s.starts_with(b"-----BEGIN PGP SIGNATURE") => Some("gpg"),
+        s if s.starts_with(b"-----BEGIN SSH SIGNATURE") => Some("ssh"),
+        s if s.starts_with(b"-----BEGIN SIGNED MESSAGE") => Some("x509"),
+        _ => None,
+    };
+    if !verify {
+        return SignatureInfo {
+            status: SignatureStatus::Unverified,
+            format,
+        };
+    }
+    let output = Command::new("git")
+        .arg("--git-dir")
+        .arg(repo.path())
+        .args(["verify-commit", "--raw"])
+        .arg(oid.to_string())
+        .output();
+    let status = match output {
+        Ok(output) if output.status.success() => SignatureStatus::Good,
+        Ok(output) => {
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            if stderr.contains("BADSIG") || stderr.contains("Signature verification failed") {
+                SignatureStatus::Bad
+            } else {
+                SignatureStatus::Unverified
+            }
+        }
+        Err(e) => {
+            warn!("验证提交 {} 的签名失败: {}", oid, e);
+            SignatureStatus::Unverified
+        }
+    };
+    SignatureInfo { status, format }
+}
+
+/// 提交列表的过滤条件
+pub struct LogQuery {
+    pub rev: String,
+    pub path: Option<String>,
+    pub since: Option<DateTime<FixedOffset>>,
+    pub until: Option<DateTime<FixedOffset>>,
+    // 作者名或邮箱中包含的文本，不区分大小写
+    pub author: Option<String>,
+    // 上一页返回的 next_cursor
+    pub cursor: Option<String>,
+    pub per_page: Option<usize>,
+    // 是否验证每个提交的签名，每个签名提交都要启动一次 git verify-commit
+    pub verify: bool,
+}
+
+/// 一页提交
+#[derive(Debug, Serialize)]
+pub struct CommitPage {
+    pub commits: Vec<CommitDetail>,
+    // 还有更多提交时，作为下一次请求的 page 参数
+    pub next_cursor: Option<String>,
+}
+
+// 分页游标：起始提交、已经遍历的提交数和当前跟踪的路径，起始提交固定后翻页结果稳定
+struct LogCursor {
+    head: Oid,
+    skip: usize,
+    path: Option<String>,
+}
+
+impl LogCursor {
+    fn encode(&self) -> String {
+        let raw = format!(
+            "{}:{}:{}",
+            self.head,
+            self.skip,
+            self.path.as_deref().unwrap_or_default()
+        );
+        URL_SAFE_NO_PAD.encode(raw)
+    }
+
+    fn decode(cursor: &str) -> Result<Self, BrowseError> {
+        let invalid = || BrowseError::Invalid("分页游标无效".to_string());
+        let raw = URL_SAFE_NO_PAD.decode(cursor).map_err(|_| invalid())?;
+        let raw = String::from_utf8(raw).map_err(|_| invalid())?;
+        let mut parts = raw.splitn(3, ':');
+        let head = parts.next().and_then(|head| Oid::from_str(head).ok());
+        let skip = parts.next().and_then(|skip| skip.parse().ok());
+        let (Some(head), Some(skip), Some(path)) = (head, skip, parts.next()) else {
+            return Err(invalid());
+        };
+        Ok(LogCursor {
+            head,
+            skip,
+            path: (!path.is_empty()).then(|| path.to_string()),
+        })
+    }
+}
+
+/// 解析 RFC 3339 时间或 `YYYY-MM-DD` 日期（按 UTC 零点）
+pub fn parse_time(value: &str) -> Result<DateTime<FixedOffset>, BrowseError> {
+    if let Ok(time) = DateTime::parse_from_rfc3339(value) {
+        return Ok(time);
+    }
+    chrono::NaiveDate::parse_from_str(value, "%Y-%m-%d")
+        .ok()
+        .and_then(|date| date.and_hms_opt(0, 0, 0))
+        .map(|time| time.and_utc().fixed_offset())
+        .ok_or_else(|| BrowseError::Invalid(format!("时间格式无效: {}", value)))
+}
+
+fn entry_id(tree: &Tree, path: &str) -> Option<Oid> {
+    tree.get_path(Path::new(path)).ok().map(|entry| entry.id())
+}
+
+// 文件在提交中新增时，检查它是否由第一个父提交中的其他文件重命名而来
+fn renamed_from(
+    repo: &Repository,
+    parent: &Tree,
+    tree: &Tree,
+    path: &str,
+) -> Result<Option<String>, git2::Error> {
+    let mut diff = repo.diff_tree_to_tree(Some(parent), Some(tree), None)?;
+    diff.find_similar(Some(git2::DiffFindOptions::new().renames(true)))?;
+    Ok(diff
+        .deltas()
+        .filter(|delta| delta.status() == git2::Delta::Renamed)
+        .find(|delta| delta.new_file().path() == Some(Path::new(path)))
+        .and_then(|delta| {
+            delta
+                .old_file()
+                .path()
+                .map(|old| old.to_string_lossy().into_owned())
+        }))
+}
+
+/// 按时间倒序列出提交
+///
+/// 指定路径时只返回改动了该路径的提交（合并提交与某个父提交相同则跳过），
+/// 路径是文件时与 `git log --follow` 一样跟随重命名。
+pub fn list_commits(repo: &Repository, query: &LogQuery) -> Result<CommitPage, BrowseError> {
+    let per_page = query
+        .per_page
+        .unwrap_or(DEFAULT_PER_PAGE)
+        .clamp(1, MAX_PER_PAGE);
+    let cursor = match &query.cursor {
+        Some(cursor) => LogCursor::decode(cursor)?,
+        None => LogCursor {
+            head: resolve_commit(repo, &query.rev)
+                .ok_or_else(|| BrowseError::NotFound(format!("引用 {} 不存在", query.rev)))?
+                .id(),
+            skip: 0,
+            path: query
+                .path
+                .as_deref()
+                .map(|path| path.trim_matches('/').to_string())
+                .filter(|path| !path.is_empty()),
+        },
+    };
+    let author = query.author.as_deref().map(str::to_lowercase);
+
+    let mut walk = repo.revwalk()?;
+    walk.set_sorting(git2::Sort::TIME)?;
+    walk.push(cursor.head)?;
+
+    let mut path = cursor.path;
+    let mut commits = Vec::new();
+    for (walked, oid) in (cursor.skip..).zip(walk.skip(cursor.skip)) {
+        // 本页已满且还有下一个提交
+        if commits.len() == per_page {
+            return Ok(CommitPage {
+                commits,
+                next_cursor: Some(
+                    LogCursor {
+                        head: cursor.head,
+                        skip: walked,
+                        path,
+                    }
+                    .encode(),
+                ),
+            });
+        }
+        let commit = repo.find_commit(oid?)?;
+
+        let mut renamed = None;
+        if let Some(path) = &path {
+            let tree = commit.tree()?;
+            let id = entry_id(&tree, path);
+            let parents = commit
+                .parents()
+                .map(|parent| parent.tree())
+                .collect::<Result<Vec<_>, _>>()?;
+            let touched = match parents.is_empty() {
+                true => id.is_some(),
+                false => parents.iter().all(|parent| entry_id(parent, path) != id),
+            };
+            if !touched {
+                continue;
+            }
+            // 只有文件跟随重命名
+            let is_file = tree
+                .get_path(Path::new(path))
+                .is_ok_and(|entry| entry.kind() == Some(ObjectType::Blob));
+            if let Some(parent) = parents.first().filter(|_| is_file)
+                && entry_id(parent, path).is_none()
+            {
+                renamed = renamed_from(repo, parent, &tree, path)?;
+            }
+        }
+
+        let committed_at = git_time(commit.committer().when());
+        // 按时间排序遍历，之后的提交都比 since 更早
+        if query.since.is_some_and(|since| committed_at < since) {
+            break;
+        }
+        let in_range = query.until.is_none_or(|until| committed_at <= until);
+        let author_matches = author.as_deref().is_none_or(|author| {
+            let signature = commit.author();
+            let name = signature.name().unwrap_or_default().to_lowercase();
+            let email = signature.email().unwrap_or_default().to_lowercase();
+            name.contains(author) || email.contains(author)
+        });
+        if in_range && author_matches {
+            let mut detail = CommitDetail::from_commit(repo, &commit, query.verify);
+            detail.path = path.clone();
+            commits.push(detail);
+        }
+        // 更早的提交中跟踪重命名前的路径
+        if let Some(old) = renamed {
+            path = Some(old);
+        }
+    }
+    Ok(CommitPage {
+        commits,
+        next_cursor: None,
+    })
+}
+
+// JSON 差异中最多返回的行数，超过后其余文件只给出统计
+const MAX_DIFF_LINES: usize = 20_000;
+// 比较两个版本时最多列出的提交数
+const MAX_COMPARE_COMMITS: usize = 250;
+
+/// 差异中的一行
+#[derive(Debug, Serialize)]
+pub struct DiffLine {
+    // "+"、"-" 或 " "
+    pub origin: char,
+    pub old_lineno: Option<u32>,
+    pub new_lineno: Option<u32>,
+    pub content: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct DiffHunk {
+    pub header: String,
+    pub old_start: u32,
+    pub old_lines: u32,
+    pub new_start: u32,
+    pub new_lines: u32,
+    pub lines: Vec<DiffLine>,
+}
+
+/// 单个文件的差异
+#[derive(Debug, Serialize)]
+pub struct FileDiff {
+    // added、deleted、modified、renamed、copied 或 typechange
+    pub status: &'static str,
+    pub old_path: Option<String>,
+    pub new_path: Option<String>,
+    pub old_oid: String,
+    pub new_oid: String,
+    pub old_mode: String,
+    pub new_mode: String,
+    pub binary: bool,
+    pub additions: usize,
+    pub deletions: usize,
+    pub hunks: Vec<DiffHunk>,
+}
+
+#[derive(Debug, Default, Serialize)]
+pub struct DiffStats {
+    pub files_changed: usize,
+    pub additions: usize,
+    pub deletions: usize,
+}
+
+#[derive(Debug, Serialize)]
+pub struct DiffResult {
+    pub stats: DiffStats,
+    pub files: Vec<FileDiff>,
+    // 超过行数上限时为 true，后面的文件没有 hunks
+    pub truncated: bool,
+}
+
+/// 单个提交及其相对第一个父提交的差异
+#[derive(Debug, Serialize)]
+pub struct CommitWithDiff {
+    pub commit: CommitDetail,
+    #[serde(flatten)]
+    pub diff: DiffResult,
+}
+
+/// 两个版本的比较
+#[derive(Debug, Serialize)]
+pub struct Comparison {
+    pub base: String,
+    pub head: String,
+    // 三点比较时差异以共同祖先为起点
+    pub merge_base: Option<String>,
+    pub ahead_by: usize,
+    pub behind_by: usize,
+    // head 中有而 base 中没有的提交，按时间倒序
+    pub commits: Vec<CommitSummary>,
+    #[serde(flatten)]
+    pub diff: DiffResult,
+}
+
+fn delta_status(status: git2::Delta) -> &'static str {
+    match status {
+        git2::Delta::Added => "added",
+        git2::Delta::Deleted => "deleted",
+        git2::Delta::Renamed => "renamed",
+        git2::Delta::Copied => "copied",
+        git2::Delta::Typechange => "typechange",
+        _ => "modified",
+    }
+}
+
+fn file_path(file: &git2::DiffFile) -> Option<String> {
+    file.path().map(|path| path.to_string_lossy().into_owned())
+}
+
+/// 比较两个目录树并检测重命名，`old` 为空时与空树比较
+///
+/// libgit2 输出补丁时只给未修改的复制写 `copy from` 头，有修改的复制会被 `git apply`
+/// 当成对源文件的修改，所以输出补丁文本时应关闭 `copies`。
+pub fn diff_trees<'r>(
+    repo: &'r Repository,
+    old: Option<&Tree>,
+    new: &Tree,
+    context_lines: u32,
+    copies: bool,
+) -> Result<git2::Diff<'r>, BrowseError> {
+    let mut options = git2::DiffOptions::new();
+    // 二进制文件输出为 git 二进制补丁，而不是 "Binary files differ"
+    options.context_lines(context_lines).show_binary(true);
+    let mut diff = repo.diff_tree_to_tree(old, Some(new), Some(&mut options))?;
+    diff.find_similar(Some(
+        git2::DiffFindOptions::new().renames(true).copies(copies),
+    ))?;
+    Ok(diff)
+}
+
+/// 提交相对第一个父提交的差异，根提交与空树比较
+pub fn commit_diff<'r>(
+    repo: &'r Repository,
+    commit: &Commit,
+    context_lines: u32,
+    copies: bool,
+) -> Result<git2::Diff<'r>, BrowseError> {
+    let parent = match commit.parent(0) {
+        Ok(parent) => Some(parent.tree()?),
+        Err(_) => None,
+    };
+    diff_trees(
+        repo,
+        parent.as_ref(),
+        &commit.tree()?,
+        context_lines,
+        copies,
+    )
+}
+
+/// 转换为按文件组织的结构化差异
+pub fn diff_json(diff: &git2::Diff) -> Result<DiffResult, BrowseError> {
+    let mut result = DiffResult {
+        stats: DiffStats::default(),
+        files: Vec::new(),
+        truncated: false,
+    };
+    let mut budget = MAX_DIFF_LINES;
+    for index in 0..diff.deltas().len() {
+        let patch = git2::Patch::from_diff(diff, index)?;
+        let delta = match &patch {
+            Some(patch) => patch.delta(),
+            None => diff.get_delta(index).expect("差异序号越界"),
+        };
+        let (_, additions, deletions) = match &patch {
+            Some(patch) => patch.line_stats()?,
+            None => (0, 0, 0),
+        };
+        let mut file = FileDiff {
+            status: delta_status(delta.status()),
+            old_path: file_path(&delta.old_file()).filter(|_| delta.status() != git2::Delta::Added),
+            new_path: file_path(&delta.new_file())
+                .filter(|_| delta.status() != git2::Delta::Deleted),
+            old_oid: delta.old_file().id().to_string(),
+            new_oid: delta.new_file().id().to_string(),
+            old_mode: format!("{:06o}", u32::from(delta.old_file().mode())),
+            new_mode: format!("{:06o}", u32::from(delta.new_file().mode())),
+            binary: delta.flags().is_binary(),
+            additions,
+            deletions,
+            hunks: Vec::new(),
+        };
+        result.stats.files_changed += 1;
+        result.stats.additions += additions;
+        result.stats.deletions += deletions;
+
+        if let Some(patch) = patch.filter(|_| !file.binary) {
+            if result.truncated || additions + deletions > budget {
+                result.truncated = true;
+                result.files.push(file);
+                continue;
+            }
+            for hunk_index in 0..patch.num_hunks() {
+                let (hunk, line_count) = patch.hunk(hunk_index)?;
+                let mut lines = Vec::with_capacity(line_count);
+                for line_index in 0..line_count {
+                    let line = patch.line_in_hunk(hunk_index, line_index)?;
+                    // 只保留增删和上下文行，忽略文件末尾无换行等标记
+                    if !matches!(line.origin(), '+' | '-' | ' ') {
+                        continue;
+                    }
+                    let content = String::from_utf8_lossy(line.content());
+                    lines.push(DiffLine {
+                        origin: line.origin(),
+                        old_lineno: line.old_lineno(),
+                        new_lineno: line.new_lineno(),
+                        content: content.trim_end_matches(['\n', '\r']).to_string(),
+                    });
+                }
+                file.hunks.push(DiffHunk {
+                    header: String::from_utf8_lossy(hunk.header())
+                        .trim_end()
+                        .to_string(),
+                    old_start: hunk.old_start(),
+                    old_lines: hunk.old_lines(),
+                    new_start: hunk.new_start(),
+                    new_lines: hunk.new_lines(),
+                    lines,
+                });
+            }
+            budget -= additions + deletions;
+        }
+        result.files.push(file);
+    }
+    Ok(result)
+}
+
+/// 统一格式（unified diff）的文本，可以直接用于 `git apply`
+pub fn diff_text(diff: &git2::Diff) -> Result<Vec<u8>, BrowseError> {
+    let mut out = Vec::new();
+    diff.print(git2::DiffFormat::Patch, |_, _, line| {
+        if matches!(line.origin(), '+' | '-' | ' ') {
+            out.push(line.origin() as u8);
+        }
+        out.extend_from_slice(line.content());
+        true
+    })?;
+    Ok(out)
+}
+
+/// 与 `git show` 相同的提交头，放在差异文本之前
+pub fn commit_header(commit: &Commit) -> String {
+    let author = commit.author();
+    let mut header = format!(
+        "commit {}\nAuthor: {} <{}>\nDate:   {}\n\n",
+        commit.id(),
+        author.name().unwrap_or_default(),
+        author.email().unwrap_or_default(),
+        git_time(author.when()).to_rfc2822()
+    );
+    for line in String::from_utf8_lossy(commit.message_bytes()).lines() {
+        header.push_str("    ");
+        header.push_str(line);
+        header.push('\n');
+    }
+    header.push('\n');
+    header
+}
+
+/// 比较范围的两端
+pub struct CompareRange<'r> {
+    pub base: Commit<'r>,
+    pub head: Commit<'r>,
+    pub merge_base: Option<Oid>,
+    // 差异的起点：三点比较时是共同祖先，否则是 base
+    pub from: Tree<'r>,
+}
+
+/// 解析 `base...head`（从共同祖先开始比较）或 `base..head`（直接比较两个版本）
+pub fn compare_range<'r>(
+    repo: &'r Repository,
+    spec: &str,
+) -> Result<CompareRange<'r>, BrowseError> {
+    let (base_rev, head_rev, three_dot) = match spec.split_once("...") {
+        Some((base, head)) => (base, head, true),
+        None => match spec.split_once("..") {
+            Some((base, head)) => (base, head, false),
+            None => {
+                return Err(BrowseError::Invalid(
+                    "比较范围应为 base...head 或 base..head".to_string(),
+                ));
+            }
+        },
+    };
+    let base = resolve_commit(repo, base_rev)
+        .ok_or_else(|| BrowseError::NotFound(format!("引用 {} 不存在", base_rev)))?;
+    let head = resolve_commit(repo, head_rev)
+        .ok_or_else(|| BrowseError::NotFound(format!("引用 {} 不存在", head_rev)))?;
+    let merge_base = repo.merge_base(base.id(), head.id()).ok();
+    // 没有共同祖先时退化为直接比较
+    let from = match merge_base.filter(|_| three_dot) {
+        Some(oid) => repo.find_commit(oid)?.tree()?,
+        None => base.tree()?,
+    };
+    Ok(CompareRange {
+        base,
+        head,
+        merge_base,
+        from,
+    })
+}
+
+/// 比较结果：领先落后的提交数、head 独有的提交和差异
+pub fn comparison(
+    repo: &Repository,
+    range: &CompareRange,
+    diff: &git2::Diff,
+) -> Result<Comparison, BrowseError> {
+    let (base, head) = (range.base.id(), range.head.id());
+    let (ahead_by, behind_by) = repo.graph_ahead_behind(head, base)?;
+    let mut walk = repo.revwalk()?;
+    walk.set_sorting(git2::Sort::TIME)?;
+    walk.push(head)?;
+    walk.hide(base)?;
+    let commits = walk
+        .take(MAX_COMPARE_COMMITS)
+        .map(|oid| Ok(CommitSummary::from_commit(&repo.find_commit(oid?)?)))
+        .collect::<Result<Vec<_>, git2::Error>>()?;
+    Ok(Comparison {
+        base: base.to_string(),
+        head: head.to_string(),
+        merge_base: range.merge_base.map(|oid| oid.to_string()),
+        ahead_by,
+        behind_by,
+        commits,
+        diff: diff_json(diff)?,
+    })
+}
+
+// 一次追溯最多返回的行数，未指定范围的大文件只处理开头部分
+const MAX_BLAME_LINES: usize = 2_000;
+
+/// 追溯的行范围和选项，行号从 1 开始，包含两端
+pub struct BlameQuery {
+    pub start: Option<usize>,
+    pub end: Option<usize>,
+    pub ignore_whitespace: bool,
+}
+
+/// 由同一个提交引入的连续行
+#[derive(Debug, Serialize)]
+pub struct BlameRange {
+    pub start_line: usize,
+    pub end_line: usize,
+    pub commit: CommitSummary,
+    // 该提交中这些行所在的文件和起始行，文件重命名后与请求的路径不同
+    pub orig_path: Option<String>,
+    pub orig_start_line: usize,
+    // 追溯到了历史的起点，这些行可能来自更早的提交
+    pub boundary: bool,
+    pub lines: Vec<String>,
+}
+
+/// 文件的追溯结果
+#[derive(Debug, Serialize)]
+pub struct BlameResult {
+    pub path: String,
+    pub commit: String,
+    pub total_lines: usize,
+    pub ranges: Vec<BlameRange>,
+    // 范围超过单次上限时下一次请求的起始行
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub next_start: Option<usize>,
+}
+
+/// 追溯文件每一行的最后修改提交
+///
+/// 只对请求的行范围计算，libgit2 逐个提交读取该文件的历史版本，不会载入整个仓库。
+pub fn blame(
+    repo: &Repository,
+    commit: &Commit,
+    path: &str,
+    query: &BlameQuery,
+) -> Result<BlameResult, BrowseError> {
+    let blob = read_blob(repo, commit, path)?;
+    if blob.symlink || is_binary(blob.content()) {
+        return Err(BrowseError::Invalid(format!("{} 不是文本文件", path)));
+    }
+    let lines: Vec<&[u8]> = blob
+        .content()
+        .split_inclusive(|&byte| byte == b'\n')
+        .collect();
+    let total_lines = lines.len();
+
+    let start = query.start.unwrap_or(1);
+    let end = query.end.unwrap_or(total_lines).min(total_lines);
+    if start == 0 || (total_lines > 0 && start > end) {
+        return Err(BrowseError::Invalid(format!(
+            "行范围 {}-{} 无效，文件共 {} 行",
+            start, end, total_lines
+        )));
+    }
+    let mut result = BlameResult {
+        path: path.to_string(),
+        commit: commit.id().to_string(),
+        total_lines,
+        ranges: Vec::new(),
+        next_start: None,
+    };
+    if total_lines == 0 {
+        return Ok(result);
+    }
+    let limit = end.min(start + MAX_BLAME_LINES - 1);
+    if limit < end {
+        result.next_start = Some(limit + 1);
+    }
+
+    let mut options = git2::BlameOptions::new();
+    options
+        .newest_commit(commit.id())
+        .min_line(start)
+        .max_line(limit)
+        .ignore_whitespace(query.ignore_whitespace);
+    let blame = repo.blame_file(Path::new(path), Some(&mut options))?;
+
+    let mut summaries: HashMap<Oid, CommitSummary> = HashMap::new();
+    for hunk in blame.iter() {
+        let first = hunk.final_start_line();
+        let last = first + hunk.lines_in_hunk() - 1;
+        // 范围外的部分不返回
+        let (first, last) = (first.max(start), last.min(limit));
+        if first > last {
+            continue;
+        }
+        let oid = hunk.final_commit_id();
+        let summary = match summaries.get(&oid) {
+            Some(summary) => summary.clone(),
+            None => {
+                let summary = CommitSummary::from_commit(&repo.find_commit(oid)?);
+                summaries.insert(oid, summary.clone());
+                summary
+            }
+        };
+        result.ranges.push(BlameRange {
+            start_line: first,
+            end_line: last,
+            commit: summary,
+            orig_path: hunk.path().map(|path| path.to_string_lossy().into_owned()),
+            orig_start_line: hunk.orig_start_line() + (first - hunk.final_start_line()),
+            boundary: hunk.is_boundary(),
+            lines: lines[first - 1..last]
+                .iter()
+                .map(|line| {
+                    let line = line.strip_suffix(b"\n").unwrap_or(line);
+                    let line = line.strip_suffix(b"\r").unwrap_or(line);
+                    String::from_utf8_lossy(line).into_owned()
+                })
+                .collect(),
+        });
+    }
+    Ok(result)
+}
+
+/// 归档格式，由请求路径的后缀决定
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ArchiveFormat {
+    TarGz,
+    Zip,
+}
+
+impl ArchiveFormat {
+    // 支持的后缀，与 git archive 的 --format 一一对应
+    const SUFFIXES: [(&'static str, ArchiveFormat); 3] = [
+        (".tar.gz", ArchiveFormat::TarGz),
+        (".tgz", ArchiveFormat::TarGz),
+        (".zip", ArchiveFormat::Zip),
+    ];
+
+    pub fn extension(&self) -> &'static str {
+        match self {
+            ArchiveFormat::TarGz => "tar.gz",
+            ArchiveFormat::Zip => "zip",
+        }
+    }
+
+    pub fn content_type(&self) -> &'static str {
+        match self {
+            ArchiveFormat::TarGz => "application/gzip",
+            ArchiveFormat::Zip => "application/zip",
+        }
+    }
+}
+
+/// 把 `{ref}.tar.gz` 或 `{ref}.zip` 拆成引用和格式
+pub fn archive_spec(spec: &str) -> Result<(&str, ArchiveFormat), BrowseError> {
+    ArchiveFormat::SUFFIXES
+        .iter()
+        .find_map(|(suffix, format)| {
+            spec.strip_suffix(suffix)
+                .filter(|rev| !rev.is_empty())
+                .map(|rev| (rev, *format))
+        })
+        .ok_or_else(|| {
+            BrowseError::Invalid(format!("{} 不是支持的归档格式，应为 .tar.gz 或 .zip", spec))
+        })
+}
+
+/// 归档内的前缀目录，未指定时与 GitHub 一致为 `{仓库名}-{引用}/`
+///
+/// 传入空字符串表示不加前缀，文件直接位于归档根目录。
+pub fn archive_prefix(
+    repo_name: &str,
+    rev: &str,
+    prefix: Option<&str>,
+) -> Result<String, BrowseError> {
+    let prefix = match prefix {
+        Some(prefix) => prefix.trim_matches('/').to_string(),
+        None => {
+            let name = repo_name.rsplit('/').next().unwrap_or(repo_name);
+            format!("{}-{}", name, rev.replace('/', "-"))
+        }
+    };
+    if prefix.is_empty() {
+        return Ok(prefix);
+    }
+    if prefix
+        .split('/')
+        .any(|segment| segment.is_empty() || segment == "." || segment == "..")
+        || prefix.contains(['\\', '\0'])
+    {
+        return Err(BrowseError::Invalid(format!("归档前缀 {} 非法", prefix)));
+    }
+    Ok(format!("{}/", prefix))
+}
+
+/// 启动 `git archive` 子进程，归档内容从标准输出读取
+///
+/// 属性从被归档的树中读取，`.gitattributes` 的 `export-ignore` 和 `export-subst` 都会生效。
+pub fn spawn_archive(
+    repo: &Repository,
+    commit: &Commit,
+    format: ArchiveFormat,
+    prefix: &str,
+) -> Result<std::process::Child, BrowseError> {
+    let child = Command::new("git")
+        .arg("--git-dir")
+        .arg(repo.path())
+        .arg("archive")
+        .arg(format!("--format={}", format.extension()))
+        .arg(format!("--prefix={}", prefix))
+        .arg(commit.id().to_string())
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()?;
+    Ok(child)
+}
+
+/// 分支及其相对默认分支的位置
+#[derive(Debug, Serialize)]
+pub struct BranchInfo {
+    pub name: String,
+    pub target: String,
+    pub commit: CommitSummary,
+    pub default: bool,
+    // 默认分支不存在时为空
+    pub ahead_by: Option<usize>,
+    pub behind_by: Option<usize>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct BranchListing {
+    // HEAD 指向的分支，仓库为空时该分支可能还不存在
+    pub default_branch: Option<String>,
+    pub branches: Vec<BranchInfo>,
+}
+
+/// 列出裸仓库的全部本地分支，按名称排序
+pub fn list_branches(repo: &Repository) -> Result<BranchListing, BrowseError> {
+    let default_branch = repo
+        .find_reference("HEAD")
+        .ok()
+        .and_then(|head| head.symbolic_target().map(str::to_string))
+        .and_then(|target| target.strip_prefix("refs/heads/").map(str::to_string));
+    let default_oid = default_branch.as_deref().and_then(|name| {
+        repo.find_branch(name, git2::BranchType::Local)
+            .ok()
+            .and_then(|branch| branch.get().target())
+    });
+
+    let mut branches = Vec::new();
+    for branch in repo.branches(Some(git2::BranchType::Local))? {
+        let (branch, _) = branch?;
+        let Some(name) = branch.name()?.map(str::to_string) else {
+            warn!("跳过名称不是 UTF-8 的分支");
+            continue;
+        };
+        let commit = branch.get().peel_to_commit()?;
+        let (ahead_by, behind_by) = match default_oid {
+            Some(base) => {
+                let (ahead, behind) = repo.graph_ahead_behind(commit.id(), base)?;
+                (Some(ahead), Some(behind))
+            }
+            None => (None, None),
+        };
+        branches.push(BranchInfo {
+            default: default_branch.as_deref() == Some(name.as_str()),
+            name,
+            target: commit.id().to_string(),
+            commit: CommitSummary::from_commit(&commit),
+            ahead_by,
+            behind_by,
+        });
+    }
+    branches.sort_by(|a, b| a.name.cmp(&b.name));
+    Ok(BranchListing {
+        default_branch,
+        branches,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // 依次创建提交时间为 times 的提交，返回最后一个提交
+    fn linear_history(repo: &Repository, times: &[i64]) -> Oid {
+        let tree_id = repo.treebuilder(None).unwrap().write().unwrap();
+        let tree = repo.find_tree(tree_id).unwrap();
+        let mut parent: Option<Oid> = None;
+        for (i, time) in times.iter().enumerate() {
+            let when = git2::Time::new(*time, 0);
+            let signature = git2::Signature::new("test", "test@localhost", &when).unwrap();
+            let parents: Vec<Commit> = parent
+                .map(|id| repo.find_commit(id).unwrap())
+                .into_iter()
+                .collect();
+            let parents: Vec<&Commit> = parents.iter().collect();
+            let message = format!("commit {}", i);
+            parent = Some(
+                repo.commit(
+                    Some("refs/heads/main"),
+                    &signature,
+                    &signature,
+                    &message,
+                    &tree,
+                    &parents,
+                )
+                .unwrap(),
+            );
+        }
+        parent.unwrap()
+    }
+
+    fn log_query(since: Option<i64>, until: Option<i64>) -> LogQuery {
+        let at = |secs: i64| DateTime::from_timestamp(secs, 0).unwrap().fixed_offset();
+        LogQuery {
+            rev: "main".to_string(),
+            path: None,
+            since: since.map(at),
+            until: until.map(at),
+            author: None,
+            cursor: None,
+            per_page: Some(2),
+            verify: false,
+        }
+    }
+
+    #[test]
+    fn since_stops_the_walk() {
+        let dir = tempfile::tempdir().unwrap();
+        let repo = Repository::init_bare(dir.path()).unwrap();
+        linear_history(&repo, &[1_000, 2_000, 3_000, 4_000, 5_000]);
+
+        let page = list_commits(&repo, &log_query(Some(3_000), None)).unwrap();
+        let messages: Vec<&str> = page.commits.iter().map(|c| c.message.as_str()).collect();
+        assert_eq!(messages, ["commit 4", "commit 3"]);
+        let cursor = page.next_cursor.expect("还有 commit 2");
+
+        // 第二页遇到早于 since 的提交后结束，不再返回游标
+        let mut query = log_query(Some(3_000), None);
+        query.cursor = Some(cursor);
+        let page = list_commits(&repo, &query).unwrap();
+        let messages: Vec<&str> = page.commits.iter().map(|c| c.message.as_str()).collect();
+        assert_eq!(messages, ["commit 2"]);
+        assert!(page.next_cursor.is_none());
+
+        let page = list_commits(&repo, &log_query(Some(1_500), Some(3_500))).unwrap();
+        let messages: Vec<&str> = page.commits.iter().map(|c| c.message.as_str()).collect();
+        assert_eq!(messages, ["commit 2", "commit 1"]);
+    }
+
+    #[test]
+    fn unsigned_commits_are_reported_without_verifying() {
+        let dir = tempfile::tempdir().unwrap();
+        let repo = Repository::init_bare(dir.path()).unwrap();
+        let head = linear_history(&repo, &[1_000]);
+        let commit = repo.find_commit(head).unwrap();
+        let detail = CommitDetail::from_commit(&repo, &commit, true);
+        assert_eq!(detail.signature.status, SignatureStatus::Unsigned);
+        assert!(detail.signature.format.is_none());
+    }
+}
@@ -0,0 +1,504 @@
+use crate::service::settings_service;
+use actix_web::{HttpResponse, ResponseError};
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD as BASE64_STANDARD;
+use git2::build::TreeUpdateBuilder;
+use git2::{Commit, FileMode, ObjectType, Oid, Repository, Tree};
+use log::info;
+use serde::Deserialize;
+use std::collections::HashSet;
+use std::path::Path;
+use thiserror::Error;
+
+// 一次提交最多包含的文件操作数
+const MAX_ACTIONS: usize = 1_000;
+
+#[derive(Debug, Error)]
+pub enum CommitError {
+    #[error("请求非法: {0}")]
+    Invalid(String),
+    #[error("{0}")]
+    NotFound(String),
+    #[error("{0}")]
+    Conflict(String),
+    #[error("{0}")]
+    Forbidden(String),
+    #[error("写入仓库失败: {0}")]
+    Git(#[from] git2::Error),
+}
+
+impl ResponseError for CommitError {
+    fn status_code(&self) -> actix_web::http::StatusCode {
+        match self {
+            CommitError::Invalid(_) => actix_web::http::StatusCode::BAD_REQUEST,
+            CommitError::NotFound(_) => actix_web::http::StatusCode::NOT_FOUND,
+            CommitError::Conflict(_) => actix_web::http::StatusCode::CONFLICT,
+            CommitError::Forbidden(_) => actix_web::http::StatusCode::FORBIDDEN,
+            CommitError::Git(_) => actix_web::http::StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        HttpResponse::build(self.status_code()).body(self.to_string())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ActionKind {
+    // 新建文件，路径已存在时失败
+    Create,
+    // 修改已有文件
+    Update,
+    // 不存在时新建，存在时修改，multipart 上传的文件使用这种方式
+    Upsert,
+    Delete,
+    // 从 previous_path 移动到 path，可以同时修改内容
+    Move,
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ContentEncoding {
+    #[default]
+    Text,
+    Base64,
+}
+
+/// JSON 请求中的一个文件操作
+#[derive(Debug, Deserialize)]
+pub struct FileAction {
+    pub action: ActionKind,
+    pub path: String,
+    pub previous_path: Option<String>,
+    // delete 时忽略，move 时省略表示内容不变
+    pub content: Option<String>,
+    #[serde(default)]
+    pub encoding: ContentEncoding,
+    // 省略时沿用原文件的模式，新文件为普通文件
+    pub executable: Option<bool>,
+}
+
+/// JSON 形式的提交请求
+#[derive(Debug, Deserialize)]
+pub struct CommitRequest {
+    pub branch: String,
+    pub message: String,
+    // 分支当前应指向的提交，不一致时拒绝，避免覆盖别人的修改
+    pub expected_parent: Option<String>,
+    pub actions: Vec<FileAction>,
+}
+
+/// 解码后的文件操作
+#[derive(Debug)]
+pub struct FileChange {
+    pub action: ActionKind,
+    pub path: String,
+    pub previous_path: Option<String>,
+    pub content: Option<Vec<u8>>,
+    pub executable: Option<bool>,
+}
+
+impl FileAction {
+    pub fn into_change(self) -> Result<FileChange, CommitError> {
+        let content = match (self.content, self.encoding) {
+            (Some(content), ContentEncoding::Text) => Some(content.into_bytes()),
+            (Some(content), ContentEncoding::Base64) => {
+                Some(BASE64_STANDARD.decode(content.trim()).map_err(|e| {
+                    CommitError::Invalid(format!("{} 的内容不是合法的 base64: {}", self.path, e))
+                })?)
+            }
+            (None, _) => None,
+        };
+        Ok(FileChange {
+            action: self.action,
+            path: self.path,
+            previous_path: self.previous_path,
+            content,
+            executable: self.executable,
+        })
+    }
+}
+
+/// 新提交的参数
+pub struct NewCommit {
+    pub branch: String,
+    pub message: String,
+    pub expected_parent: Option<String>,
+    pub changes: Vec<FileChange>,
+}
+
+// 规范化仓库内路径，拒绝 `..`、`.git` 等会逃出仓库或破坏仓库的路径
+fn normalize_path(path: &str) -> Result<String, CommitError> {
+    let trimmed = path.trim_matches('/');
+    let invalid = trimmed.is_empty()
+        || trimmed.contains('\0')
+        || trimmed.split('/').any(|segment| {
+            segment.is_empty()
+                || segment == "."
+                || segment == ".."
+                || segment.eq_ignore_ascii_case(".git")
+        });
+    if invalid {
+        return Err(CommitError::Invalid(format!("文件路径 {:?} 非法", path)));
+    }
+    Ok(trimmed.to_string())
+}
+
+// 基准树中的文件，返回 OID 和模式；目录和子模块不能作为文件操作
+fn existing_file(tree: &Tree, path: &str) -> Result<Option<(Oid, i32)>, CommitError> {
+    let Ok(entry) = tree.get_path(Path::new(path)) else {
+        return Ok(None);
+    };
+    match entry.kind() {
+        Some(ObjectType::Blob) => Ok(Some((entry.id(), entry.filemode()))),
+        Some(ObjectType::Tree) => Err(CommitError::Invalid(format!("{} 是目录", path))),
+        _ => Err(CommitError::Invalid(format!("{} 是子模块", path))),
+    }
+}
+
+fn file_mode(executable: Option<bool>, existing: Option<i32>) -> FileMode {
+    match (executable, existing) {
+        (Some(true), _) => FileMode::BlobExecutable,
+        (Some(false), _) => FileMode::Blob,
+        (None, Some(mode)) if mode == i32::from(FileMode::BlobExecutable) => {
+            FileMode::BlobExecutable
+        }
+        (None, Some(mode)) if mode == i32::from(FileMode::Link) => FileMode::Link,
+        _ => FileMode::Blob,
+    }
+}
+
+// 把文件操作应用到基准树上，返回新树
+fn build_tree<'r>(
+    repo: &'r Repository,
+    base: &Tree,
+    changes: &[FileChange],
+) -> Result<Tree<'r>, CommitError> {
+    let mut builder = TreeUpdateBuilder::new();
+    let mut touched = HashSet::new();
+    for change in changes {
+        let path = normalize_path(&change.path)?;
+        let previous = change
+            .previous_path
+            .as_deref()
+            .map(normalize_path)
+            .transpose()?;
+        // 同一路径只能出现一次，否则结果取决于操作顺序
+        for path in std::iter::once(&path).chain(previous.as_ref()) {
+            if !touched.insert(path.clone()) {
+                return Err(CommitError::Invalid(format!("{} 出现在多个操作中", path)));
+            }
+        }
+        let existing = existing_file(base, &path)?;
+        let content = |required: bool| -> Result<Option<Oid>, CommitError> {
+            match &change.content {
+                Some(content) => Ok(Some(repo.blob(content)?)),
+                None if required => Err(CommitError::Invalid(format!("{} 缺少文件内容", path))),
+                None => Ok(None),
+            }
+        };
+
+        match change.action {
+            ActionKind::Create | ActionKind::Update | ActionKind::Upsert => {
+                match (change.action, existing) {
+                    (ActionKind::Create, Some(_)) => {
+                        return Err(CommitError::Invalid(format!("{} 已存在", path)));
+                    }
+                    (ActionKind::Update, None) => {
+                        return Err(CommitError::Invalid(format!("{} 不存在", path)));
+                    }
+                    _ => {}
+                }
+                let oid = content(true)?.expect("内容已检查");
+                let mode = file_mode(change.executable, existing.map(|(_, mode)| mode));
+                builder.upsert(path.as_str(), oid, mode);
+            }
+            ActionKind::Delete => {
+                if existing.is_none() {
+                    return Err(CommitError::Invalid(format!("{} 不存在", path)));
+                }
+                builder.remove(path.as_str());
+            }
+            ActionKind::Move => {
+                let Some(previous) = previous else {
+                    return Err(CommitError::Invalid(format!(
+                        "移动 {} 时缺少 previous_path",
+                        path
+                    )));
+                };
+                let Some((old_oid, old_mode)) = existing_file(base, &previous)? else {
+                    return Err(CommitError::Invalid(format!("{} 不存在", previous)));
+                };
+                if existing.is_some() {
+                    return Err(CommitError::Invalid(format!("{} 已存在", path)));
+                }
+                let oid = content(false)?.unwrap_or(old_oid);
+                builder.remove(previous.as_str());
+                builder.upsert(
+                    path.as_str(),
+                    oid,
+                    file_mode(change.executable, Some(old_mode)),
+                );
+            }
+        }
+    }
+    // 路径的上级是文件等情况由 libgit2 拒绝
+    let oid = builder
+        .create_updated(repo, base)
+        .map_err(|e| CommitError::Invalid(format!("无法生成目录树: {}", e.message())))?;
+    Ok(repo.find_tree(oid)?)
+}
+
+// 分支不存在时只有 HEAD 指向的未出生分支（空仓库）可以直接创建根提交
+fn branch_parent<'r>(
+    repo: &'r Repository,
+    branch: &str,
+) -> Result<Option<Commit<'r>>, CommitError> {
+    let refname = format!("refs/heads/{}", branch);
+    if let Ok(reference) = repo.find_reference(&refname) {
+        return Ok(Some(reference.peel_to_commit()?));
+    }
+    let head_target = repo
+        .find_reference("HEAD")
+        .ok()
+        .and_then(|head| head.symbolic_target().map(str::to_string));
+    if head_target.as_deref() == Some(refname.as_str()) {
+        return Ok(None);
+    }
+    Err(CommitError::NotFound(format!("分支 {} 不存在", branch)))
+}
+
+/// 直接在裸仓库中生成提交并移动分支，不经过工作区
+///
+/// 分支只在仍指向读取时的提交时才会更新，并发写入时后到的请求得到冲突。
+pub fn commit_changes<'r>(
+    repo: &'r Repository,
+    request: &NewCommit,
+    author: &git2::Signature,
+) -> Result<Commit<'r>, CommitError> {
+    let branch = request.branch.as_str();
+    if !git2::Branch::name_is_valid(branch)? {
+        return Err(CommitError::Invalid(format!("分支名 {} 非法", branch)));
+    }
+    if request.message.trim().is_empty() {
+        return Err(CommitError::Invalid("提交说明不能为空".to_string()));
+    }
+    if request.changes.is_empty() {
+        return Err(CommitError::Invalid("没有文件操作".to_string()));
+    }
+    if request.changes.len() > MAX_ACTIONS {
+        return Err(CommitError::Invalid(format!(
+            "文件操作不能超过 {} 个",
+            MAX_ACTIONS
+        )));
+    }
+    if settings_service::is_protected_branch(repo, branch)? {
+        return Err(CommitError::Forbidden(format!(
+            "分支 {} 受保护，不能直接提交",
+            branch
+        )));
+    }
+
+    let parent = branch_parent(repo, branch)?;
+    if let Some(expected) = request.expected_parent.as_deref() {
+        let matches = parent.as_ref().is_some_and(|parent| {
+            repo.revparse_single(expected)
+                .is_ok_and(|object| object.id() == parent.id())
+        });
+        if !matches {
+            return Err(CommitError::Conflict(format!(
+                "分支 {} 当前指向 {}，与期望的 {} 不一致",
+                branch,
+                parent
+                    .as_ref()
+                    .map(|parent| parent.id().to_string())
+                    .unwrap_or_else(|| "空".to_string()),
+                expected
+            )));
+        }
+    }
+
+    let base = match &parent {
+        Some(parent) => parent.tree()?,
+        None => repo.find_tree(repo.treebuilder(None)?.write()?)?,
+    };
+    let tree = build_tree(repo, &base, &request.changes)?;
+    if tree.id() == base.id() {
+        return Err(CommitError::Invalid("提交没有产生任何变化".to_string()));
+    }
+
+    let parents: Vec<&Commit> = parent.iter().collect();
+    let oid = repo.commit(None, author, author, &request.message, &tree, &parents)?;
+    let refname = format!("refs/heads/{}", branch);
+    let log_message = format!(
+        "commit (api): {}",
+        request.message.lines().next().unwrap_or_default()
+    );
+    // 以读取到的提交为条件更新引用，期间分支被移动时失败
+    let updated = match &parent {
+        Some(parent) => repo.reference_matching(&refname, oid, true, parent.id(), &log_message),
+        None => repo.reference(&refname, oid, false, &log_message),
+    };
+    updated.map_err(|e| match e.code() {
+        git2::ErrorCode::Modified | git2::ErrorCode::Exists => {
+            CommitError::Conflict(format!("分支 {} 已被其他写入更新，请重试", branch))
+        }
+        _ => CommitError::Git(e),
+    })?;
+    info!(
+        "通过接口提交到 {}: {} ({} 个文件操作)",
+        branch,
+        oid,
+        request.changes.len()
+    );
+    Ok(repo.find_commit(oid)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn change(action: ActionKind, path: &str, content: Option<&str>) -> FileChange {
+        FileChange {
+            action,
+            path: path.to_string(),
+            previous_path: None,
+            content: content.map(|content| content.as_bytes().to_vec()),
+            executable: None,
+        }
+    }
+
+    fn moved(previous: &str, path: &str, content: Option<&str>) -> FileChange {
+        FileChange {
+            previous_path: Some(previous.to_string()),
+            ..change(ActionKind::Move, path, content)
+        }
+    }
+
+    // 临时裸仓库和一棵包含 README.md、src/main.rs 的基准树
+    fn base_tree() -> (tempfile::TempDir, Repository, Oid) {
+        let dir = tempfile::tempdir().unwrap();
+        let repo = Repository::init_bare(dir.path()).unwrap();
+        let empty = repo.treebuilder(None).unwrap().write().unwrap();
+        let tree = {
+            let empty = repo.find_tree(empty).unwrap();
+            let changes = [
+                change(ActionKind::Create, "README.md", Some("readme")),
+                change(ActionKind::Create, "src/main.rs", Some("fn main() {}")),
+            ];
+            build_tree(&repo, &empty, &changes).unwrap().id()
+        };
+        (dir, repo, tree)
+    }
+
+    fn file(repo: &Repository, tree: &Tree, path: &str) -> Option<(String, i32)> {
+        let entry = tree.get_path(Path::new(path)).ok()?;
+        let blob = repo.find_blob(entry.id()).unwrap();
+        Some((
+            String::from_utf8(blob.content().to_vec()).unwrap(),
+            entry.filemode(),
+        ))
+    }
+
+    fn apply<'r>(
+        repo: &'r Repository,
+        base: Oid,
+        changes: &[FileChange],
+    ) -> Result<Tree<'r>, CommitError> {
+        build_tree(repo, &repo.find_tree(base).unwrap(), changes)
+    }
+
+    #[test]
+    fn normalizes_paths() {
+        assert_eq!(normalize_path("/docs/a.md/").unwrap(), "docs/a.md");
+        assert_eq!(normalize_path("a.git.md").unwrap(), "a.git.md");
+        assert_eq!(normalize_path(".github/ci.yml").unwrap(), ".github/ci.yml");
+        for path in [
+            "",
+            "/",
+            "a//b",
+            "./a",
+            "a/./b",
+            "../a",
+            "a/../../b",
+            ".git/config",
+            "a/.GIT/hooks/pre-receive",
+            "a\0b",
+        ] {
+            assert!(
+                matches!(normalize_path(path), Err(CommitError::Invalid(_))),
+                "{:?} 应被拒绝",
+                path
+            );
+        }
+    }
+
+    #[test]
+    fn applies_each_action() {
+        let (_dir, repo, base) = base_tree();
+        let changes = [
+            change(ActionKind::Update, "README.md", Some("new readme")),
+            change(ActionKind::Upsert, "docs/guide.md", Some("guide")),
+            moved("src/main.rs", "src/bin/app.rs", None),
+        ];
+        let tree = apply(&repo, base, &changes).unwrap();
+        assert_eq!(file(&repo, &tree, "README.md").unwrap().0, "new readme");
+        assert_eq!(file(&repo, &tree, "docs/guide.md").unwrap().0, "guide");
+        assert_eq!(
+            file(&repo, &tree, "src/bin/app.rs").unwrap().0,
+            "fn main() {}"
+        );
+        assert!(file(&repo, &tree, "src/main.rs").is_none());
+
+        let changes = [
+            change(ActionKind::Delete, "README.md", None),
+            FileChange {
+                executable: Some(true),
+                ..change(ActionKind::Upsert, "src/main.rs", Some("#!/bin/sh"))
+            },
+        ];
+        let tree = apply(&repo, base, &changes).unwrap();
+        assert!(file(&repo, &tree, "README.md").is_none());
+        let (content, mode) = file(&repo, &tree, "src/main.rs").unwrap();
+        assert_eq!(content, "#!/bin/sh");
+        assert_eq!(mode, i32::from(FileMode::BlobExecutable));
+    }
+
+    #[test]
+    fn rejects_conflicting_actions() {
+        let (_dir, repo, base) = base_tree();
+        let rejected = [
+            vec![change(ActionKind::Create, "README.md", Some("x"))],
+            vec![change(ActionKind::Update, "missing.md", Some("x"))],
+            vec![change(ActionKind::Update, "README.md", None)],
+            vec![change(ActionKind::Delete, "missing.md", None)],
+            vec![change(ActionKind::Delete, "src", None)],
+            vec![change(ActionKind::Upsert, "src", Some("x"))],
+            vec![change(ActionKind::Move, "b.md", None)],
+            vec![moved("missing.md", "b.md", None)],
+            vec![moved("README.md", "src/main.rs", None)],
+            // 上级路径是文件
+            vec![change(ActionKind::Create, "README.md/child", Some("x"))],
+            // 同一路径出现在多个操作中
+            vec![
+                change(ActionKind::Update, "README.md", Some("x")),
+                change(ActionKind::Delete, "README.md", None),
+            ],
+            vec![
+                moved("README.md", "docs/README.md", None),
+                change(ActionKind::Create, "README.md", Some("x")),
+            ],
+        ];
+        for changes in &rejected {
+            assert!(
+                matches!(apply(&repo, base, changes), Err(CommitError::Invalid(_))),
+                "{:?} 应被拒绝",
+                changes
+            );
+        }
+        // 被拒绝的操作不影响基准树
+        let tree = repo.find_tree(base).unwrap();
+        assert_eq!(file(&repo, &tree, "README.md").unwrap().0, "readme");
+    }
+}
@@ -1,50 +1,138 @@
 use crate::controller::git_controller::SepFileRequest;
+use crate::repo::repo_path::{RepoPath, resolve_under};
+use crate::service::mirror_service::{self, DivergencePolicy, RefChange};
+use crate::service::quota_service::{self, QuotaError};
+use crate::service::{credential_service, push_mirror_service, ssh_service, template_service};
 use anyhow::{Context, Result, anyhow};
 use git2::{
-    BranchType, Cred, FetchOptions, PushOptions, RemoteCallbacks, Repository, build::RepoBuilder,
+    BranchType, FetchOptions, PushOptions, RemoteCallbacks, Repository, RepositoryInitOptions,
+    build::RepoBuilder,
 };
 use log::{error, info, warn};
-use secrecy::{ExposeSecret, Secret};
+use serde::Serialize;
+use std::cell::Cell;
 use std::error::Error;
 use std::fs;
 use std::path::{Path, PathBuf};
+use std::sync::PoisonError;
+use std::time::Instant;
 use walkdir::WalkDir;
 
-static TEST_REPOS: &str = "test_repos";
-static BARE_REPOS: &str = "bare_repos";
+pub static TEST_REPOS: &str = "test_repos";
+pub static BARE_REPOS: &str = "bare_repos";
 
-pub fn init_repo(repo_name: String) -> Result<(), Box<dyn Error>> {
-    let repo_name = format!("{}/{}", TEST_REPOS, repo_name);
-    if Path::new(&repo_name).exists() {
+/// 网络传输进度的观察者，返回 false 时中止传输，后台任务用它上报进度和响应取消
+pub trait ProgressObserver {
+    /// 对象接收和索引进度
+    fn transfer(&self, stats: &git2::Progress<'_>) -> bool;
+    /// 远端通过 sideband 输出的文本，例如 "Counting objects: 50% (4/8)"
+    fn sideband(&self, message: &str) -> bool;
+}
+
+/// 把远端的 sideband 输出转给观察者
+pub fn observe_sideband<'a>(
+    callbacks: &mut RemoteCallbacks<'a>,
+    progress: Option<&'a dyn ProgressObserver>,
+) {
+    if let Some(progress) = progress {
+        callbacks.sideband_progress(move |data| progress.sideband(&String::from_utf8_lossy(data)));
+    }
+}
+
+/// 初始化工作仓库和对应的裸仓库，可选地套用服务端模板
+///
+/// 两个仓库都会写入 `[repo_config]` 和模板中的配置并安装模板钩子；
+/// 模板中的文件作为初始提交写入工作仓库，再同步到裸仓库。
+pub fn init_repo(repo_name: &RepoPath, template: Option<&str>) -> Result<(), Box<dyn Error>> {
+    let repo_path = repo_name.work_dir(Path::new(TEST_REPOS))?;
+    let bare_path = repo_name.bare_dir(Path::new(BARE_REPOS))?;
+    if repo_path.exists() || bare_path.exists() {
         // 初始化仓库
         return Err(anyhow!("当前{}仓库已存在:", repo_name).into());
     }
-    Repository::init(repo_name)?;
+    let template = template.map(template_service::load_template).transpose()?;
+    let config_entries = template_service::repo_config_entries(template.as_ref());
+    let initial_branch = config_entries
+        .iter()
+        .find(|(key, _)| key == "init.defaultBranch")
+        .map(|(_, value)| value.as_str())
+        .unwrap_or("master");
+
+    let mut init_options = RepositoryInitOptions::new();
+    init_options.initial_head(initial_branch);
+    let repo = Repository::init_opts(&repo_path, &init_options)?;
+    template_service::apply_config(&repo, &config_entries)?;
     info!("仓库已初始化！");
 
+    fs::create_dir_all(&bare_path)?;
+    let bare_repo = Repository::init_opts(&bare_path, init_options.bare(true))?;
+    template_service::apply_config(&bare_repo, &config_entries)?;
+    configure_bare_repo(&bare_path)?;
+    info!("裸仓库已初始化: {}", bare_path.display());
+
+    if let Some(template) = &template {
+        template.write_files(&repo_path, repo_name)?;
+        template.install_hooks(&repo)?;
+        template.install_hooks(&bare_repo)?;
+        commit_all(
+            &repo,
+            &format!("Initial commit from template {}", template.name),
+        )?;
+        sync_bare_repo(&repo_path, &bare_path, None)?;
+        info!("已套用模板 {}", template.name);
+    }
+
     Ok(())
 }
 
-pub fn clone_with_token(
+// 把工作目录中的全部文件提交到当前分支
+fn commit_all(repo: &Repository, message: &str) -> Result<git2::Oid, Box<dyn Error>> {
+    let mut index = repo.index()?;
+    index.add_all(["*"].iter(), git2::IndexAddOption::DEFAULT, None)?;
+    index.write()?;
+    let tree = repo.find_tree(index.write_tree()?)?;
+
+    // 没有配置 user.name / user.email 时使用服务端身份
+    let signature = repo
+        .signature()
+        .or_else(|_| git2::Signature::now("git-demo", "git-demo@localhost"))?;
+    let parent = match repo.head() {
+        Ok(head) => Some(head.peel_to_commit()?),
+        Err(_) => None,
+    };
+    let parents: Vec<&git2::Commit> = parent.iter().collect();
+    let oid = repo.commit(
+        Some("HEAD"),
+        &signature,
+        &signature,
+        message,
+        &tree,
+        &parents,
+    )?;
+    info!("创建提交 {}", oid);
+    Ok(oid)
+}
+
+pub fn clone_with_credentials(
     url: &str,
-    repo_name: &Path,
-    token: Secret<String>,
+    repo_name: &RepoPath,
+    progress: Option<&dyn ProgressObserver>,
 ) -> Result<Repository, Box<dyn Error>> {
-    // 验证 URL 格式
-    if !url.starts_with("https://") {
-        warn!("⚠️ 建议使用 HTTPS URL 进行令牌认证");
-    }
-
-    // 创建回调函数
-    let mut callbacks = RemoteCallbacks::new();
-
-    // 设置认证回调
-    callbacks.credentials(move |_url, username, _allowed| {
-        // 使用令牌进行认证
-        // 用户名可以是 "token" 或任意非空字符串
-        let username = username.unwrap_or("token");
-        Cred::userpass_plaintext(username, token.expose_secret())
+    // 克隆前检查配额，已经超限的仓库或命名空间直接拒绝
+    quota_service::check_quota(repo_name, 0)?;
+    let remaining = quota_service::remaining_bytes(repo_name)?;
+    let received = Cell::new(0u64);
+
+    // 创建回调函数，按仓库或主机从凭据登记中选择凭据
+    let mut callbacks = credential_service::remote_callbacks(url, Some(repo_name))?;
+
+    // 接收的数据超过剩余配额时取消克隆
+    callbacks.transfer_progress(|stats| {
+        received.set(stats.received_bytes() as u64);
+        remaining.is_none_or(|limit| received.get() <= limit)
+            && progress.is_none_or(|progress| progress.transfer(&stats))
     });
+    observe_sideband(&mut callbacks, progress);
 
     // 配置获取选项
     let mut fetch_options = FetchOptions::new();
@@ -55,7 +143,7 @@ pub fn clone_with_token(
     builder.fetch_options(fetch_options);
 
     // 构建最终的克隆路径
-    let full_clone_path = PathBuf::from(TEST_REPOS).join(repo_name);
+    let full_clone_path = repo_name.work_dir(Path::new(TEST_REPOS))?;
 
     // 执行克隆
     match builder.clone(url, &full_clone_path) {
@@ -71,6 +159,13 @@ pub fn clone_with_token(
         }
         Err(e) => {
             error!("克隆失败: {}", e.message());
+            if let Some(limit) = remaining.filter(|limit| received.get() > *limit) {
+                return Err(QuotaError::TransferTooLarge {
+                    received: received.get(),
+                    remaining: limit,
+                }
+                .into());
+            }
             Err(anyhow!("Git 操作失败: {}", e.message()).into())
         }
     }
@@ -100,55 +195,103 @@ pub fn search_all_repo(root_path: &str) -> Result<Vec<String>, Box<dyn Error>> {
     Ok(repos)
 }
 
-/// 获取远程仓库分支列表
+/// 远程仓库中的一个引用
+#[derive(Debug, Serialize)]
+pub struct RemoteRef {
+    pub name: String,
+    pub oid: String,
+    // 附注标签指向的提交
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub peeled: Option<String>,
+}
+
+/// ls-remote 的结果
+#[derive(Debug, Default, Serialize)]
+pub struct RemoteRefs {
+    // 远程 HEAD 指向的分支，空仓库或服务端未告知时为 None
+    pub head: Option<String>,
+    pub branches: Vec<RemoteRef>,
+    pub tags: Vec<RemoteRef>,
+}
+
+/// 列出远程仓库的分支和标签，不下载任何对象
+///
+/// `pattern` 支持 `*` 和 `?` 通配符，匹配短名称（如 `release/*`）或完整引用名。
 pub fn fetch_remote_branches(
     url: &str,
-    token: Secret<String>,
-) -> Result<Vec<String>, Box<dyn Error>> {
+    repo_name: Option<&RepoPath>,
+    pattern: Option<&str>,
+) -> Result<RemoteRefs, Box<dyn Error>> {
+    if is_local_url(url) {
+        return Err(anyhow!("不支持本地路径或 file:// 地址: {}", url).into());
+    }
     // 创建带认证的回调
-    let mut callbacks = RemoteCallbacks::new();
-    callbacks.credentials(move |_url, username, _allowed| {
-        let username = username.unwrap_or("token");
-        Cred::userpass_plaintext(username, token.expose_secret())
-    });
-
-    // 创建临时目录用于克隆
-    let temp_dir = tempfile::tempdir()?;
-    let temp_path = temp_dir.path();
-
-    // 配置获取选项
-    let mut fetch_options = FetchOptions::new();
-    fetch_options.remote_callbacks(callbacks);
-
-    // 配置仓库构建器
-    let mut builder = RepoBuilder::new();
-    builder.fetch_options(fetch_options);
-
-    // 执行浅克隆(只获取元数据)
-    let repo = builder.clone(url, temp_path)?;
-
-    // 获取远程分支
-    let mut branches = Vec::new();
-    for branch in repo.branches(Some(BranchType::Remote))? {
-        let (branch, _) = branch?;
-        if let Some(name) = branch.name()? {
-            // 移除"origin/"前缀
-            // let clean_name = name.replace("origin/", "");
-            // info!("分支:{}", clean_name);
-            branches.push(name.to_string());
+    let callbacks = credential_service::remote_callbacks(url, repo_name)?;
+    let mut remote = git2::Remote::create_detached(url)?;
+    let connection = remote.connect_auth(git2::Direction::Fetch, Some(callbacks), None)?;
+
+    let matches = |short: &str, name: &str| {
+        pattern.is_none_or(|pattern| {
+            ssh_service::glob_match(pattern, short) || ssh_service::glob_match(pattern, name)
+        })
+    };
+    let mut refs = RemoteRefs {
+        head: connection.default_branch().ok().and_then(|head| {
+            head.as_str()
+                .map(|head| head.trim_start_matches("refs/heads/").to_string())
+        }),
+        ..Default::default()
+    };
+    for head in connection.list()? {
+        let name = head.name();
+        let oid = head.oid().to_string();
+        if let Some(short) = name.strip_prefix("refs/heads/") {
+            if matches(short, name) {
+                refs.branches.push(RemoteRef {
+                    name: short.to_string(),
+                    oid,
+                    peeled: None,
+                });
+            }
+        } else if let Some(short) = name.strip_prefix("refs/tags/") {
+            // 附注标签的 "^{}" 条目紧跟在标签之后
+            if let Some(tag) = short.strip_suffix("^{}") {
+                if let Some(last) = refs.tags.last_mut().filter(|last| last.name == tag) {
+                    last.peeled = Some(oid);
+                }
+            } else if matches(short, name) {
+                refs.tags.push(RemoteRef {
+                    name: short.to_string(),
+                    oid,
+                    peeled: None,
+                });
+            }
         }
     }
+    info!(
+        "远程仓库 {} 共 {} 个分支、{} 个标签",
+        url,
+        refs.branches.len(),
+        refs.tags.len()
+    );
+    Ok(refs)
+}
 
-    Ok(branches)
+/// 本地路径或 file:// 地址，这类地址会读取服务器上的任意仓库，不允许作为远程地址
+///
+/// scp 风格的 `user@host:path` 冒号前不含 `/`，其余没有 scheme 的地址都按本地路径处理。
+pub fn is_local_url(url: &str) -> bool {
+    match url.split_once("://") {
+        Some((scheme, _)) => scheme.eq_ignore_ascii_case("file"),
+        None => !url
+            .split_once(':')
+            .is_some_and(|(host, _)| !host.is_empty() && !host.contains('/')),
+    }
 }
 
 pub fn check_path(filerequest: &SepFileRequest) -> Result<PathBuf, Box<dyn Error>> {
-    if filerequest.file_path.contains("..") || Path::new(&filerequest.file_path).is_absolute() {
-        warn!("检测到路径遍历攻击: {}", filerequest.file_path);
-        // return HttpResponse::BadRequest().body("Invalid file path");
-        return Err(anyhow!("Invalid file path{}", filerequest.repo_name).into());
-    }
-    let repo_path = std::path::PathBuf::from("test_repos").join(&filerequest.repo_name);
+    let repo_name = RepoPath::parse(&filerequest.repo_name)?;
+    let repo_path = repo_name.work_dir(Path::new(TEST_REPOS))?;
     //打开仓库
     let repo = Repository::open(&repo_path)?;
     //获取当前分支
@@ -158,7 +301,7 @@ pub fn check_path(filerequest: &SepFileRequest) -> Result<PathBuf, Box<dyn Error
         .ok_or_else(|| anyhow!("无法获取当前分支名称"))?;
     info!("当前分支: {}", branch_name);
     // 检查分支名称是否匹配
-    if branch_name != &filerequest.branch_name {
+    if branch_name != filerequest.branch_name {
         return Err(anyhow!(
             "分支不匹配: 当前分支 {}, 请求分支 {}",
             branch_name,
@@ -166,36 +309,120 @@ pub fn check_path(filerequest: &SepFileRequest) -> Result<PathBuf, Box<dyn Error
         )
         .into());
     }
-    //文件完整路径
-    let full_path = std::path::PathBuf::from("test_repos")
-        .join(&filerequest.repo_name)
-        .join(&filerequest.file_path);
+    //文件完整路径，拒绝 `..`、绝对路径以及指向仓库外的符号链接
+    let full_path = resolve_under(&repo_path, Path::new(&filerequest.file_path))
+        .inspect_err(|_| warn!("检测到路径遍历攻击: {}", filerequest.file_path))?;
 
     Ok(full_path)
 }
 
+/// 拉取前后需要对比的工作仓库引用
+const PULL_REF_PREFIXES: &[&str] = &["refs/heads/", "refs/remotes/origin/", "refs/tags/"];
+
+/// 裸仓库同步步骤的结果
+#[derive(Debug, Serialize)]
+#[serde(tag = "status", rename_all = "snake_case")]
+pub enum BareSyncResult {
+    // 工作仓库没有变化，未同步
+    Skipped,
+    Succeeded,
+    Failed { error: String },
+}
+
+/// 一次拉取的结果
+#[derive(Debug, Serialize)]
+pub struct PullOutcome {
+    pub repo: String,
+    pub branch: String,
+    pub message: String,
+    // 本地分支与上游分叉时采用的策略，未分叉时为空
+    pub policy: Option<DivergencePolicy>,
+    // 合并或变基无法完成时冲突的文件
+    pub conflicts: Vec<String>,
+    // 工作仓库中发生变化的引用
+    pub refs: Vec<RefChange>,
+    // 本次并入当前分支的上游提交数
+    pub commits_pulled: usize,
+    pub received_objects: usize,
+    pub received_bytes: usize,
+    pub duration_ms: u64,
+    pub bare_sync: BareSyncResult,
+}
+
+impl PullOutcome {
+    /// 本地分支没有跟上上游，需要人工处理
+    pub fn needs_attention(&self) -> bool {
+        self.policy == Some(DivergencePolicy::Keep) || !self.conflicts.is_empty()
+    }
+}
+
+// 把上游提交并入当前分支的结果
+struct Integration {
+    message: String,
+    policy: Option<DivergencePolicy>,
+    conflicts: Vec<String>,
+    // 当前分支是否移动过，移动过才需要同步裸仓库
+    changed: bool,
+    // 重置和变基会改写历史，需要强制更新裸仓库中的该分支
+    rewritten: bool,
+}
+
+impl Integration {
+    fn new(message: String, policy: Option<DivergencePolicy>, changed: bool) -> Self {
+        info!("{}", message);
+        Integration {
+            message,
+            policy,
+            conflicts: Vec::new(),
+            changed,
+            rewritten: false,
+        }
+    }
+}
+
 // 从私有远程仓库拉取更新
-pub fn pull_with_token(repo_path: &str, token: Secret<String>) -> Result<String, Box<dyn Error>> {
-    let full_repo_path = format!("{}/{}", TEST_REPOS, repo_path);
+//
+// 本地分支与上游分叉时按裸仓库 config 中的 mirror.diverged 处理，默认保留本地提交并报告。
+pub fn pull_with_credentials(
+    repo_name: &RepoPath,
+    progress: Option<&dyn ProgressObserver>,
+) -> Result<PullOutcome, Box<dyn Error>> {
+    // 与镜像同步共用同一把锁，同一仓库不会同时被两个任务拉取，锁中没有数据，中毒后照常使用
+    let lock = mirror_service::sync_lock(repo_name);
+    let _guard = lock.lock().unwrap_or_else(PoisonError::into_inner);
+    let started = Instant::now();
+    let full_repo_path = repo_name.work_dir(Path::new(TEST_REPOS))?;
     // 打开本地仓库
     let repo = Repository::open(&full_repo_path)?;
-    info!("仓库路径: {}", full_repo_path);
+    info!("仓库路径: {}", full_repo_path.display());
+    let bare_repo_path = repo_name.bare_dir(Path::new(BARE_REPOS))?; // bare_repos/zss.git
 
     // 获取当前分支名称
     let head = repo.head()?;
     let branch_name = head
         .shorthand()
-        .ok_or_else(|| anyhow!("无法获取当前分支名称"))?;
+        .ok_or_else(|| anyhow!("无法获取当前分支名称"))?
+        .to_string();
     info!("当前分支: {}", branch_name);
+    let refname = format!("refs/heads/{}", branch_name);
 
     // 获取远程仓库
     let mut remote = repo.find_remote("origin")?;
 
-    // 设置认证回调
-    let mut callbacks = RemoteCallbacks::new();
-    callbacks.credentials(move |_url, username, _allowed| {
-        Cred::userpass_plaintext(username.unwrap_or("token"), token.expose_secret())
+    // 设置认证回调，并记录传输量
+    let url = remote
+        .url()
+        .ok_or_else(|| anyhow!("远程仓库 origin 的地址无效"))?
+        .to_string();
+    let received_objects = Cell::new(0usize);
+    let received_bytes = Cell::new(0usize);
+    let mut callbacks = credential_service::remote_callbacks(&url, Some(repo_name))?;
+    callbacks.transfer_progress(|stats| {
+        received_objects.set(stats.received_objects());
+        received_bytes.set(stats.received_bytes());
+        progress.is_none_or(|progress| progress.transfer(&stats))
     });
+    observe_sideband(&mut callbacks, progress);
 
     // 配置fetch选项
     let mut fetch_options = FetchOptions::new();
@@ -203,18 +430,194 @@ pub fn pull_with_token(repo_path: &str, token: Secret<String>) -> Result<String,
         .remote_callbacks(callbacks)
         .download_tags(git2::AutotagOption::All);
 
+    let before = mirror_service::snapshot_refs(&repo, PULL_REF_PREFIXES)?;
+    let old_head = repo.refname_to_id(&refname)?;
+
     // 执行fetch (使用正确的引用规格)
     let refspec = format!(
         "+refs/heads/{}:refs/remotes/origin/{}",
         branch_name, branch_name
     );
     remote.fetch(&[&refspec], Some(&mut fetch_options), None)?;
+    drop(fetch_options);
     info!("成功获取远程更新");
 
-    // 获取FETCH_HEAD提交
-    let fetch_head = repo.find_reference("FETCH_HEAD")?;
+    // 获取刚抓取的远程跟踪分支，FETCH_HEAD 的第一条可能是同时抓取的标签
+    let fetch_head = repo.find_reference(&format!("refs/remotes/origin/{}", branch_name))?;
     let fetch_commit = repo.reference_to_annotated_commit(&fetch_head)?;
 
+    let integration = integrate_upstream(&repo, &bare_repo_path, &branch_name, &fetch_commit)?;
+
+    // 统计并入当前分支的上游提交
+    let commits_pulled = if integration.changed {
+        let mut revwalk = repo.revwalk()?;
+        revwalk.push(fetch_commit.id())?;
+        revwalk.hide(old_head)?;
+        revwalk.count()
+    } else {
+        0
+    };
+
+    //同步裸仓库
+    let bare_sync = if integration.changed {
+        let force_branch = integration.rewritten.then_some(branch_name.as_str());
+        match sync_bare_repo(&full_repo_path, &bare_repo_path, force_branch) {
+            Ok(()) => {
+                push_mirror_service::schedule(repo_name);
+                BareSyncResult::Succeeded
+            }
+            Err(e) => BareSyncResult::Failed {
+                error: e.to_string(),
+            },
+        }
+    } else {
+        BareSyncResult::Skipped
+    };
+
+    let after = mirror_service::snapshot_refs(&repo, PULL_REF_PREFIXES)?;
+    let outcome = PullOutcome {
+        repo: repo_name.to_string(),
+        branch: branch_name,
+        message: integration.message,
+        policy: integration.policy,
+        conflicts: integration.conflicts,
+        refs: mirror_service::ref_changes(&before, &after),
+        commits_pulled,
+        received_objects: received_objects.get(),
+        received_bytes: received_bytes.get(),
+        duration_ms: started.elapsed().as_millis() as u64,
+        bare_sync,
+    };
+    info!(
+        "拉取 {} 完成: 并入 {} 个提交，接收 {} 字节，耗时 {} ms，裸仓库同步: {:?}",
+        outcome.repo,
+        outcome.commits_pulled,
+        outcome.received_bytes,
+        outcome.duration_ms,
+        outcome.bare_sync
+    );
+    Ok(outcome)
+}
+
+// 把抓取到的上游提交并入当前分支，分叉时按仓库配置的策略处理
+fn integrate_upstream(
+    repo: &Repository,
+    bare_repo_path: &Path,
+    branch_name: &str,
+    fetch_commit: &git2::AnnotatedCommit,
+) -> Result<Integration, Box<dyn Error>> {
+    // 分析合并情况
+    let analysis = repo.merge_analysis(&[fetch_commit])?;
+    info!("合并分析结果: {:?}", analysis);
+
+    let refname = format!("refs/heads/{}", branch_name);
+    if analysis.0.is_up_to_date() {
+        return Ok(Integration::new(
+            "已经是最新版本，无需更新".to_string(),
+            None,
+            false,
+        ));
+    }
+    if analysis.0.is_fast_forward() {
+        info!("执行快进合并");
+        move_branch(repo, &refname, fetch_commit.id(), "Fast-Forward")?;
+        return Ok(Integration::new("成功更新工作目录".to_string(), None, true));
+    }
+
+    // 本地分支与上游分叉
+    let policy = Repository::open_bare(bare_repo_path)
+        .and_then(|bare| mirror_service::read_mirror_config(&bare))
+        .map(|mirror| mirror.diverged)
+        .unwrap_or_default();
+    warn!(
+        "⚠️ 分支 {} 与上游分叉，处理策略: {}",
+        branch_name,
+        policy.as_str()
+    );
+    let upstream = repo.find_commit(fetch_commit.id())?;
+    let local = repo.find_reference(&refname)?.peel_to_commit()?;
+    let signature = repo
+        .signature()
+        .or_else(|_| git2::Signature::now("git-demo", "git-demo@localhost"))?;
+
+    match policy {
+        DivergencePolicy::Keep => {
+            let message = format!(
+                "分支 {} 与上游分叉，已保留本地提交，需要手动处理",
+                branch_name
+            );
+            Ok(Integration::new(message, Some(policy), false))
+        }
+        DivergencePolicy::Reset => {
+            move_branch(repo, &refname, upstream.id(), "Reset to upstream")?;
+            let message = format!("分支 {} 已重置为上游 {}", branch_name, upstream.id());
+            Ok(Integration {
+                rewritten: true,
+                ..Integration::new(message, Some(policy), true)
+            })
+        }
+        DivergencePolicy::Merge => {
+            let mut index = repo.merge_commits(&local, &upstream, None)?;
+            if index.has_conflicts() {
+                return Ok(conflict_integration(&index, branch_name, policy)?);
+            }
+            let tree = repo.find_tree(index.write_tree_to(repo)?)?;
+            let message = format!("Merge upstream into {}", branch_name);
+            let oid = repo.commit(
+                None,
+                &signature,
+                &signature,
+                &message,
+                &tree,
+                &[&local, &upstream],
+            )?;
+            move_branch(repo, &refname, oid, "Merge upstream")?;
+            let message = format!("分支 {} 已与上游合并，合并提交 {}", branch_name, oid);
+            Ok(Integration::new(message, Some(policy), true))
+        }
+        DivergencePolicy::Rebase => {
+            let branch = repo.reference_to_annotated_commit(&repo.find_reference(&refname)?)?;
+            let mut options = git2::RebaseOptions::new();
+            options.inmemory(true);
+            let mut rebase =
+                repo.rebase(Some(&branch), Some(fetch_commit), None, Some(&mut options))?;
+            let mut head = upstream.id();
+            while let Some(operation) = rebase.next() {
+                operation?;
+                let index = rebase.inmemory_index()?;
+                if index.has_conflicts() {
+                    let integration = conflict_integration(&index, branch_name, policy)?;
+                    rebase.abort()?;
+                    return Ok(integration);
+                }
+                match rebase.commit(None, &signature, None) {
+                    Ok(oid) => head = oid,
+                    // 上游已包含相同的改动，跳过该提交
+                    Err(e) if e.code() == git2::ErrorCode::Applied => {}
+                    Err(e) => {
+                        rebase.abort()?;
+                        return Err(e.into());
+                    }
+                }
+            }
+            rebase.finish(None)?;
+            move_branch(repo, &refname, head, "Rebase onto upstream")?;
+            let message = format!("分支 {} 已变基到上游，当前提交 {}", branch_name, head);
+            Ok(Integration {
+                rewritten: true,
+                ..Integration::new(message, Some(policy), true)
+            })
+        }
+    }
+}
+
+// 把分支移动到指定提交并强制检出工作目录
+fn move_branch(
+    repo: &Repository,
+    refname: &str,
+    target: git2::Oid,
+    log_message: &str,
+) -> Result<(), Box<dyn Error>> {
     // 准备强制checkout选项
     let mut checkout_builder = git2::build::CheckoutBuilder::new();
     checkout_builder
@@ -224,45 +627,54 @@ pub fn pull_with_token(repo_path: &str, token: Secret<String>) -> Result<String,
         .use_theirs(true)
         .recreate_missing(true); // 关键修复：确保创建缺失文件
 
-    // 分析合并情况
-    let analysis = repo.merge_analysis(&[&fetch_commit])?;
-    info!("合并分析结果: {:?}", analysis);
+    // 更新本地分支引用
+    let mut reference = repo.find_reference(refname)?;
+    reference.set_target(target, log_message)?;
 
-    if analysis.0.is_up_to_date() {
-        let message = "已经是最新版本，无需更新".to_string();
-        info!("{}", message);
-        Ok(message)
-    } else if analysis.0.is_fast_forward() {
-        info!("执行快进合并");
+    // 重置HEAD并检出
+    repo.set_head(refname)?;
+    repo.checkout_head(Some(&mut checkout_builder))?;
 
-        // 更新本地分支引用
-        let refname = format!("refs/heads/{}", branch_name);
-        let mut reference = repo.find_reference(&refname)?;
-        reference.set_target(fetch_commit.id(), "Fast-Forward")?;
-
-        // 重置HEAD并检出
-        repo.set_head(&refname)?;
-        repo.checkout_head(Some(&mut checkout_builder))?;
-
-        // 额外确保工作目录同步
-        let mut index = repo.index()?;
-        index.read(true)?;
-        index.write()?;
-
-        let message = "成功更新工作目录".to_string();
-        info!("{}", message);
-        let work_repo_path = Path::new(TEST_REPOS).join(repo_path); // test_repos/zss
-        let bare_repo_path = Path::new(BARE_REPOS).join(format!("{}.git", repo_path)); // bare_repos/zss.git
+    // 额外确保工作目录同步
+    let mut index = repo.index()?;
+    index.read(true)?;
+    index.write()?;
+    Ok(())
+}
 
-        //同步裸仓库
-        let _ = sync_bare_repo(&work_repo_path, &bare_repo_path);
-        Ok(message)
-    } else {
-        return Err(anyhow!("需要手动解决合并冲突").into());
+// 合并或变基产生冲突时不修改本地分支，只报告冲突的文件
+fn conflict_integration(
+    index: &git2::Index,
+    branch_name: &str,
+    policy: DivergencePolicy,
+) -> Result<Integration, git2::Error> {
+    let mut conflicts = Vec::new();
+    for conflict in index.conflicts()? {
+        let conflict = conflict?;
+        if let Some(entry) = conflict.our.or(conflict.their).or(conflict.ancestor) {
+            conflicts.push(String::from_utf8_lossy(&entry.path).into_owned());
+        }
     }
+    let message = format!(
+        "分支 {} 与上游存在冲突，{} 未完成，本地分支保持不变",
+        branch_name,
+        policy.as_str()
+    );
+    warn!("⚠️ {}: {:?}", message, conflicts);
+    Ok(Integration {
+        conflicts,
+        ..Integration::new(message, Some(policy), false)
+    })
 }
 
-pub fn sync_bare_repo(repo_path: &Path, bare_path: &Path) -> Result<(), Box<dyn Error>> {
+/// 把工作仓库的分支和标签推送到裸仓库
+///
+/// `force_branch` 指定的分支历史被改写过（重置或变基），需要强制更新。
+pub fn sync_bare_repo(
+    repo_path: &Path,
+    bare_path: &Path,
+    force_branch: Option<&str>,
+) -> Result<(), Box<dyn Error>> {
     info!(
         "开始同步裸仓库: {} -> {}",
         repo_path.display(),
@@ -275,7 +687,7 @@ pub fn sync_bare_repo(repo_path: &Path, bare_path: &Path) -> Result<(), Box<dyn
     }
 
     // 同步到裸仓库
-    match sync_to_bare_repo(&repo_path, bare_path) {
+    match sync_to_bare_repo(repo_path, bare_path, force_branch) {
         Ok(_) => info!("✅ 成功同步到裸仓库"),
         Err(e) => {
             warn!("⚠️ 同步到裸仓库失败: {}", e);
@@ -297,7 +709,11 @@ pub fn sync_bare_repo(repo_path: &Path, bare_path: &Path) -> Result<(), Box<dyn
 }
 
 // 同步到裸仓库
-fn sync_to_bare_repo(source_repo_path: &Path, bare_path: &Path) -> Result<(), Box<dyn Error>> {
+fn sync_to_bare_repo(
+    source_repo_path: &Path,
+    bare_path: &Path,
+    force_branch: Option<&str>,
+) -> Result<(), Box<dyn Error>> {
     // 1. 打开源仓库（工作仓库）
     let source_repo = Repository::open(source_repo_path)?;
 
@@ -345,19 +761,18 @@ fn sync_to_bare_repo(source_repo_path: &Path, bare_path: &Path) -> Result<(), Bo
     for branch in source_repo.branches(Some(BranchType::Local))? {
         let (branch, _) = branch?;
         if let Some(name) = branch.name()? {
-            let refspec = format!("refs/heads/{}:refs/heads/{}", name, name);
+            let force = if force_branch == Some(name) { "+" } else { "" };
+            let refspec = format!("{}refs/heads/{}:refs/heads/{}", force, name, name);
             refspecs.push(refspec);
             info!("准备推送分支: {}", name);
         }
     }
 
     // 推送所有标签
-    for tag_name in source_repo.tag_names(None)?.iter() {
-        if let Some(tag_name) = tag_name {
-            let refspec = format!("refs/tags/{}:refs/tags/{}", tag_name, tag_name);
-            refspecs.push(refspec);
-            info!("准备推送标签: {}", tag_name);
-        }
+    for tag_name in source_repo.tag_names(None)?.iter().flatten() {
+        let refspec = format!("refs/tags/{}:refs/tags/{}", tag_name, tag_name);
+        refspecs.push(refspec);
+        info!("准备推送标签: {}", tag_name);
     }
 
     if refspecs.is_empty() {
@@ -399,31 +814,112 @@ fn optimize_bare_repo(bare_path: &Path) -> Result<(), Box<dyn Error>> {
     Ok(())
 }
 
-/// 获取本地仓库的所有分支（本地 + 远程）
-pub fn list_branches(repo_path: &String) -> Result<Vec<String>, Box<dyn Error>> {
-    // 打开本地仓库
-    let repo = Repository::open(repo_path)?;
-    let mut branches = Vec::new();
+/// 将工作仓库切换到指定分支，用于与裸仓库的默认分支保持一致
+///
+/// 本地分支不存在时从 `origin/<branch>` 创建；工作仓库不存在时返回 `Ok(false)`。
+pub fn checkout_work_branch(repo_name: &RepoPath, branch: &str) -> Result<bool, Box<dyn Error>> {
+    let work_path = repo_name.work_dir(Path::new(TEST_REPOS))?;
+    if !work_path.exists() {
+        info!("工作仓库 {} 不存在，跳过分支切换", work_path.display());
+        return Ok(false);
+    }
+    let repo = Repository::open(&work_path)?;
+
+    if repo.find_branch(branch, BranchType::Local).is_err() {
+        let remote_branch = repo
+            .find_branch(&format!("origin/{}", branch), BranchType::Remote)
+            .map_err(|_| anyhow!("工作仓库中找不到分支 {}", branch))?;
+        let commit = remote_branch.get().peel_to_commit()?;
+        let mut local = repo.branch(branch, &commit, false)?;
+        local.set_upstream(Some(&format!("origin/{}", branch)))?;
+        info!("从 origin/{} 创建本地分支", branch);
+    }
 
-    // 1. 获取本地分支（refs/heads/）
-    let local_branches = repo.branches(Some(BranchType::Local))?;
-    print!("准备遍历");
-    for branch_result in local_branches {
-        let (branch, _branch_type) = branch_result?;
-        let branch_name = branch.name()?.unwrap_or("unnamed-local-branch");
-        print!("Found branch: {}", branch_name);
-        branches.push(branch_name.to_string());
+    let refname = format!("refs/heads/{}", branch);
+    let target = repo.revparse_single(&refname)?;
+    repo.checkout_tree(&target, Some(git2::build::CheckoutBuilder::new().safe()))?;
+    repo.set_head(&refname)?;
+    info!("工作仓库已切换到分支 {}", branch);
+    Ok(true)
+}
+
+/// 把裸仓库中分支或标签的变化同步到工作仓库，否则下次拉取后同步裸仓库时会被还原
+///
+/// `target` 为 None 表示引用已被删除；新引用的对象从裸仓库抓取。
+/// 工作仓库检出中的分支只能快进，工作区同时更新；`force` 为 true 时（镜像同步以上游为准）
+/// 检出中的分支也直接重置到 `target`。
+/// 工作仓库不存在时返回 `Ok(false)`。
+pub fn update_work_ref(
+    repo_name: &RepoPath,
+    refname: &str,
+    target: Option<git2::Oid>,
+    force: bool,
+) -> Result<bool, Box<dyn Error>> {
+    let work_path = repo_name.work_dir(Path::new(TEST_REPOS))?;
+    if !work_path.exists() {
+        info!("工作仓库 {} 不存在，跳过引用同步", work_path.display());
+        return Ok(false);
     }
+    let repo = Repository::open(&work_path)?;
+    // 空仓库的 HEAD 指向尚不存在的分支，同样视为检出中
+    let checked_out = repo
+        .find_reference("HEAD")
+        .ok()
+        .and_then(|head| head.symbolic_target().map(str::to_string))
+        .is_some_and(|head| head == refname);
+
+    let Some(target) = target else {
+        if checked_out {
+            return Err(anyhow!("工作仓库当前位于 {}，无法删除", refname).into());
+        }
+        if let Ok(mut reference) = repo.find_reference(refname) {
+            reference.delete()?;
+            info!("已从工作仓库删除引用 {}", refname);
+        }
+        return Ok(true);
+    };
 
-    Ok(branches)
+    let bare_path = repo_name.bare_dir(Path::new(BARE_REPOS))?;
+    let bare_url = format!("file://{}", bare_path.canonicalize()?.display());
+    let mut remote = repo.remote_anonymous(&bare_url)?;
+    let mut fetch_options = FetchOptions::new();
+    fetch_options.download_tags(git2::AutotagOption::None);
+    // 检出中的分支只抓取对象，引用在更新工作区之后再移动
+    let refspec = if checked_out {
+        refname.to_string()
+    } else {
+        format!("+{}:{}", refname, refname)
+    };
+    remote.fetch(&[&refspec], Some(&mut fetch_options), None)?;
+
+    if checked_out {
+        if !force
+            && let Ok(current) = repo.refname_to_id(refname)
+            && current != target
+            && !repo.graph_descendant_of(target, current)?
+        {
+            return Err(anyhow!("工作仓库的 {} 有裸仓库中没有的提交，无法快进", refname).into());
+        }
+        let commit = repo.find_commit(target)?;
+        let mut checkout = git2::build::CheckoutBuilder::new();
+        if force {
+            checkout.force();
+        } else {
+            checkout.safe();
+        }
+        repo.checkout_tree(commit.as_object(), Some(&mut checkout))?;
+        repo.reference(refname, target, true, "同步裸仓库")?;
+    }
+    info!("工作仓库已同步引用 {} -> {}", refname, target);
+    Ok(true)
 }
 
-pub fn convert_to_bare(source: &Path, repo_name: &Path) -> Result<PathBuf> {
+pub fn convert_to_bare(source: &Path, repo_name: &RepoPath) -> Result<PathBuf> {
     // 1. 准备目标路径
-    let bare_root = Path::new(BARE_REPOS);
-    fs::create_dir_all(bare_root).context("创建裸仓库目录失败")?;
-
-    let bare_path = bare_root.join(format!("{}.git", repo_name.to_string_lossy()));
+    let bare_path = repo_name.bare_dir(Path::new(BARE_REPOS))?;
+    if let Some(parent) = bare_path.parent() {
+        fs::create_dir_all(parent).context("创建裸仓库目录失败")?;
+    }
 
     // 2. 清理可能存在的旧仓库
     if bare_path.exists() {
@@ -544,7 +1040,8 @@ fn configure_bare_repo(repo_path: &Path) -> Result<()> {
     config.set_bool("core.bare", true)?;
 
     // 优化配置
-    config.set_bool("gc.auto", true)?;
+    // gc.auto 是松散对象数量阈值，必须是数字，写成 true 会让 receive-pack 的自动 gc 报错
+    config.set_i32("gc.auto", 6700)?;
     config.set_bool("repack.writeBitmaps", true)?;
     config.set_bool("receive.autogc", true)?;
     config.set_str("receive.denyNonFastForwards", "true")?;
@@ -578,3 +1075,41 @@ fn copy_dir(src: &Path, dst: &Path) -> Result<()> {
     }
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn local_urls_are_detected() {
+        for url in [
+            "/srv/git/bare_repos/secret.git",
+            "bare_repos/secret.git",
+            "./secret",
+            "../secret",
+            "file:///etc",
+            "FILE://localhost/srv/git",
+            "C/x:y",
+        ] {
+            assert!(is_local_url(url), "{:?} 应视为本地地址", url);
+        }
+        for url in [
+            "https://github.com/org/repo.git",
+            "ssh://git@example.com:2222/org/repo.git",
+            "git@github.com:org/repo.git",
+            "example.com:repo.git",
+        ] {
+            assert!(!is_local_url(url), "{:?} 应视为远程地址", url);
+        }
+    }
+
+    #[test]
+    fn fetch_remote_branches_rejects_local_paths() {
+        let dir = tempfile::tempdir().unwrap();
+        Repository::init_bare(dir.path()).unwrap();
+        let url = format!("file://{}", dir.path().display());
+        assert!(fetch_remote_branches(&url, None, None).is_err());
+        let path = dir.path().to_str().unwrap();
+        assert!(fetch_remote_branches(path, None, None).is_err());
+    }
+}
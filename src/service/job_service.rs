@@ -0,0 +1,378 @@
+use crate::config::app_config::app_config;
+use crate::service::git_service::ProgressObserver;
+use actix_web::{HttpResponse, ResponseError};
+use chrono::{DateTime, Local};
+use log::{info, warn};
+use rand::RngCore;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use thiserror::Error;
+use tokio::sync::{Semaphore, broadcast};
+
+// 每个任务的事件缓冲，订阅者落后太多时丢弃旧的进度事件
+const EVENT_CAPACITY: usize = 256;
+// 传输进度事件的最小间隔，避免每个对象都推送一次
+const PROGRESS_INTERVAL: Duration = Duration::from_millis(200);
+
+#[derive(Debug, Error)]
+pub enum JobError {
+    #[error("任务 {0} 不存在")]
+    NotFound(String),
+    #[error("任务 {0} 已经结束，无法取消")]
+    AlreadyFinished(String),
+}
+
+impl ResponseError for JobError {
+    fn status_code(&self) -> actix_web::http::StatusCode {
+        match self {
+            JobError::NotFound(_) => actix_web::http::StatusCode::NOT_FOUND,
+            JobError::AlreadyFinished(_) => actix_web::http::StatusCode::CONFLICT,
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        HttpResponse::build(self.status_code()).body(self.to_string())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum JobKind {
+    Clone,
+    Pull,
+    FetchRemoteBranches,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum JobState {
+    Queued,
+    Running,
+    Succeeded,
+    Failed,
+    Cancelled,
+}
+
+impl JobState {
+    pub fn is_finished(&self) -> bool {
+        matches!(
+            self,
+            JobState::Succeeded | JobState::Failed | JobState::Cancelled
+        )
+    }
+}
+
+/// 来自 git2 传输回调的进度
+#[derive(Debug, Default, Clone, Serialize)]
+pub struct JobProgress {
+    pub total_objects: usize,
+    pub received_objects: usize,
+    pub indexed_objects: usize,
+    pub received_bytes: usize,
+}
+
+/// 后台任务的状态
+#[derive(Debug, Clone, Serialize)]
+pub struct Job {
+    pub id: String,
+    pub kind: JobKind,
+    // 操作对象：仓库名或远程地址
+    pub target: String,
+    pub state: JobState,
+    pub progress: JobProgress,
+    pub cancel_requested: bool,
+    // 任务的返回内容，与原来同步接口的响应体一致
+    pub result: Option<serde_json::Value>,
+    pub error: Option<String>,
+    pub created_at: DateTime<Local>,
+    pub started_at: Option<DateTime<Local>>,
+    pub finished_at: Option<DateTime<Local>>,
+}
+
+/// 任务执行结果，失败时也可以带上结果（例如拉取时的冲突信息）
+pub enum JobOutcome {
+    Succeeded(serde_json::Value),
+    Failed {
+        error: String,
+        result: Option<serde_json::Value>,
+    },
+}
+
+impl JobOutcome {
+    pub fn failed(error: impl ToString) -> Self {
+        JobOutcome::Failed {
+            error: error.to_string(),
+            result: None,
+        }
+    }
+}
+
+/// 推送给 `GET /jobs/{id}/events` 订阅者的任务事件
+#[derive(Debug, Clone)]
+pub enum JobEvent {
+    // 任务开始或结束时的完整状态
+    State(Job),
+    Progress(JobProgress),
+    // 远端 sideband 输出的一行文本
+    Message(String),
+}
+
+struct JobEntry {
+    job: Job,
+    cancel: Arc<AtomicBool>,
+    events: broadcast::Sender<JobEvent>,
+}
+
+impl JobEntry {
+    // 没有订阅者时发送失败，忽略即可
+    fn publish(&self, event: JobEvent) {
+        let _ = self.events.send(event);
+    }
+}
+
+/// 传给任务的上下文，用于上报进度和检查取消
+pub struct JobContext {
+    id: String,
+    manager: Arc<JobManager>,
+    cancel: Arc<AtomicBool>,
+    last_progress: Mutex<Option<Instant>>,
+}
+
+impl JobContext {
+    pub fn is_cancelled(&self) -> bool {
+        self.cancel.load(Ordering::Relaxed)
+    }
+}
+
+/// 记录进度并推送事件，返回 false 表示任务已被取消，git2 会中止传输
+impl ProgressObserver for JobContext {
+    fn transfer(&self, stats: &git2::Progress<'_>) -> bool {
+        let progress = JobProgress {
+            total_objects: stats.total_objects(),
+            received_objects: stats.received_objects(),
+            indexed_objects: stats.indexed_objects(),
+            received_bytes: stats.received_bytes(),
+        };
+        // 限制推送频率，但接收和索引完成时总是推送
+        let done = progress.total_objects > 0
+            && progress.indexed_objects == progress.total_objects;
+        let due = {
+            let mut last = self.last_progress.lock().unwrap();
+            let due = done || last.is_none_or(|last| last.elapsed() >= PROGRESS_INTERVAL);
+            if due {
+                *last = Some(Instant::now());
+            }
+            due
+        };
+        if let Some(entry) = self.manager.jobs.lock().unwrap().get_mut(&self.id) {
+            entry.job.progress = progress.clone();
+            if due {
+                entry.publish(JobEvent::Progress(progress));
+            }
+        }
+        !self.is_cancelled()
+    }
+
+    fn sideband(&self, message: &str) -> bool {
+        // 一段输出可能包含多行，进度行以 \r 结尾
+        if let Some(entry) = self.manager.jobs.lock().unwrap().get(&self.id) {
+            message
+                .split(['\r', '\n'])
+                .map(str::trim)
+                .filter(|line| !line.is_empty())
+                .for_each(|line| entry.publish(JobEvent::Message(line.to_string())));
+        }
+        !self.is_cancelled()
+    }
+}
+
+/// 长时间运行的克隆、拉取等操作的后台队列
+///
+/// 任务在阻塞线程池中执行，同时运行的数量受 `[job_config] max_concurrent` 限制，
+/// 结束超过 `retention_secs` 的任务在提交新任务时清理。
+pub struct JobManager {
+    jobs: Mutex<HashMap<String, JobEntry>>,
+    permits: Arc<Semaphore>,
+}
+
+impl JobManager {
+    pub fn new() -> Arc<Self> {
+        let max_concurrent = app_config().job_config.max_concurrent.max(1);
+        Arc::new(Self {
+            jobs: Mutex::new(HashMap::new()),
+            permits: Arc::new(Semaphore::new(max_concurrent)),
+        })
+    }
+
+    pub fn get(&self, id: &str) -> Result<Job, JobError> {
+        self.jobs
+            .lock()
+            .unwrap()
+            .get(id)
+            .map(|entry| entry.job.clone())
+            .ok_or_else(|| JobError::NotFound(id.to_string()))
+    }
+
+    /// 全部任务，按创建时间倒序
+    pub fn list(&self) -> Vec<Job> {
+        let mut jobs: Vec<Job> = self
+            .jobs
+            .lock()
+            .unwrap()
+            .values()
+            .map(|entry| entry.job.clone())
+            .collect();
+        jobs.sort_by_key(|job| std::cmp::Reverse(job.created_at));
+        jobs
+    }
+
+    /// 请求取消任务：排队中的直接取消，运行中的在下一次传输回调时中止
+    pub fn cancel(&self, id: &str) -> Result<Job, JobError> {
+        let mut jobs = self.jobs.lock().unwrap();
+        let entry = jobs
+            .get_mut(id)
+            .ok_or_else(|| JobError::NotFound(id.to_string()))?;
+        if entry.job.state.is_finished() {
+            return Err(JobError::AlreadyFinished(id.to_string()));
+        }
+        entry.cancel.store(true, Ordering::Relaxed);
+        entry.job.cancel_requested = true;
+        if entry.job.state == JobState::Queued {
+            entry.job.state = JobState::Cancelled;
+            entry.job.finished_at = Some(Local::now());
+            entry.publish(JobEvent::State(entry.job.clone()));
+        }
+        info!("任务 {} 已请求取消", id);
+        Ok(entry.job.clone())
+    }
+
+    // 清理结束时间超过保留期的任务
+    fn prune(jobs: &mut HashMap<String, JobEntry>) {
+        let retention = chrono::Duration::seconds(app_config().job_config.retention_secs as i64);
+        let now = Local::now();
+        jobs.retain(|_, entry| {
+            entry
+                .job
+                .finished_at
+                .is_none_or(|finished| now - finished < retention)
+        });
+    }
+
+    /// 订阅任务事件，同时返回订阅时的状态；任务已经结束时不再有事件
+    pub fn subscribe(
+        &self,
+        id: &str,
+    ) -> Result<(Job, Option<broadcast::Receiver<JobEvent>>), JobError> {
+        let jobs = self.jobs.lock().unwrap();
+        let entry = jobs
+            .get(id)
+            .ok_or_else(|| JobError::NotFound(id.to_string()))?;
+        let receiver = (!entry.job.state.is_finished()).then(|| entry.events.subscribe());
+        Ok((entry.job.clone(), receiver))
+    }
+
+    // 更新任务状态并推送给订阅者
+    fn update(&self, id: &str, f: impl FnOnce(&mut Job)) {
+        if let Some(entry) = self.jobs.lock().unwrap().get_mut(id) {
+            f(&mut entry.job);
+            entry.publish(JobEvent::State(entry.job.clone()));
+        }
+    }
+
+    /// 提交任务，立即返回排队中的任务状态
+    pub fn submit<F>(self: &Arc<Self>, kind: JobKind, target: String, task: F) -> Job
+    where
+        F: FnOnce(&JobContext) -> JobOutcome + Send + 'static,
+    {
+        let mut id = [0u8; 8];
+        rand::thread_rng().fill_bytes(&mut id);
+        let id = hex::encode(id);
+        let cancel = Arc::new(AtomicBool::new(false));
+        let job = Job {
+            id: id.clone(),
+            kind,
+            target,
+            state: JobState::Queued,
+            progress: JobProgress::default(),
+            cancel_requested: false,
+            result: None,
+            error: None,
+            created_at: Local::now(),
+            started_at: None,
+            finished_at: None,
+        };
+        {
+            let mut jobs = self.jobs.lock().unwrap();
+            Self::prune(&mut jobs);
+            jobs.insert(
+                id.clone(),
+                JobEntry {
+                    job: job.clone(),
+                    cancel: cancel.clone(),
+                    events: broadcast::channel(EVENT_CAPACITY).0,
+                },
+            );
+        }
+        info!("任务 {} 已提交: {:?} {}", id, kind, job.target);
+
+        let manager = self.clone();
+        tokio::spawn(async move {
+            let Ok(_permit) = manager.permits.clone().acquire_owned().await else {
+                return;
+            };
+            // 排队期间被取消或已被清理时不再执行
+            {
+                let mut jobs = manager.jobs.lock().unwrap();
+                match jobs.get_mut(&id) {
+                    Some(entry) if entry.job.state == JobState::Queued => {
+                        entry.job.state = JobState::Running;
+                        entry.job.started_at = Some(Local::now());
+                        entry.publish(JobEvent::State(entry.job.clone()));
+                    }
+                    _ => return,
+                }
+            }
+
+            let context = JobContext {
+                id: id.clone(),
+                manager: manager.clone(),
+                cancel,
+                last_progress: Mutex::new(None),
+            };
+            let (outcome, cancelled) = tokio::task::spawn_blocking(move || {
+                let outcome = task(&context);
+                (outcome, context.is_cancelled())
+            })
+            .await
+            .unwrap_or_else(|e| (JobOutcome::failed(format!("任务异常退出: {}", e)), false));
+
+            manager.update(&id, |job| {
+                job.finished_at = Some(Local::now());
+                match outcome {
+                    JobOutcome::Succeeded(result) => {
+                        job.state = JobState::Succeeded;
+                        job.result = Some(result);
+                    }
+                    // 取消导致传输中止时记为已取消，git2 此时的错误信息没有意义
+                    JobOutcome::Failed { .. } if cancelled => {
+                        job.state = JobState::Cancelled;
+                        job.error = Some("任务已取消".to_string());
+                    }
+                    JobOutcome::Failed { error, result } => {
+                        job.state = JobState::Failed;
+                        job.error = Some(error);
+                        job.result = result;
+                    }
+                }
+                match job.state {
+                    JobState::Succeeded => info!("任务 {} 完成", job.id),
+                    state => warn!("任务 {} 结束: {:?} {:?}", job.id, state, job.error),
+                }
+            });
+        });
+        job
+    }
+}
@@ -0,0 +1,276 @@
+use crate::config::app_config::app_config;
+use crate::repo::repo_path::RepoPath;
+use crate::service::mirror_service::{self, MirrorConfig};
+use chrono::{DateTime, Local};
+use log::{info, warn};
+use rand::Rng;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::sync::Notify;
+
+/// 单个镜像的调度状态
+#[derive(Debug, Clone, Serialize)]
+pub struct MirrorStatus {
+    pub repo: String,
+    pub interval_secs: u64,
+    pub running: bool,
+    pub last_run: Option<DateTime<Local>>,
+    pub last_success: Option<DateTime<Local>>,
+    pub last_result: Option<String>,
+    pub last_error: Option<String>,
+    pub last_error_at: Option<DateTime<Local>>,
+    pub consecutive_failures: u32,
+    pub next_run: DateTime<Local>,
+    // 运行期间收到立即同步请求，结束后马上再执行一次
+    pub queued: bool,
+}
+
+impl MirrorStatus {
+    fn new(repo_name: &RepoPath, mirror: &MirrorConfig, next_run: DateTime<Local>) -> Self {
+        Self {
+            repo: repo_name.to_string(),
+            interval_secs: mirror.interval_secs,
+            running: false,
+            last_run: None,
+            last_success: None,
+            last_result: None,
+            last_error: None,
+            last_error_at: None,
+            consecutive_failures: 0,
+            next_run,
+            queued: false,
+        }
+    }
+}
+
+/// 镜像后台同步调度器
+///
+/// 每个 tick 检查已登记的镜像，到期的在阻塞线程池中执行同步。
+/// 成功后按间隔加随机抖动排期，失败后按指数退避重试。
+///
+/// 登记信息缓存在内存中，只在 `registration_changed` 和每 `rescan_secs` 一次的全量扫描时更新。
+pub struct MirrorScheduler {
+    statuses: Mutex<HashMap<String, MirrorStatus>>,
+    registrations: Mutex<Registrations>,
+    notify: Notify,
+}
+
+#[derive(Default)]
+struct Registrations {
+    mirrors: HashMap<String, (RepoPath, MirrorConfig)>,
+    // 上一次全量扫描的时间，None 表示还没有扫描过
+    scanned_at: Option<Instant>,
+}
+
+fn jitter() -> chrono::Duration {
+    let max = app_config().mirror_config.jitter_secs;
+    let secs = if max == 0 {
+        0
+    } else {
+        rand::thread_rng().gen_range(0..=max)
+    };
+    chrono::Duration::seconds(secs as i64)
+}
+
+/// 第 n 次连续失败后的等待时间：retry_base * 2^(n-1)，不超过 max_backoff
+fn backoff(failures: u32) -> chrono::Duration {
+    let config = &app_config().mirror_config;
+    let exp = failures.saturating_sub(1).min(31);
+    let secs = config
+        .retry_base_secs
+        .saturating_mul(1u64 << exp)
+        .min(config.max_backoff_secs);
+    chrono::Duration::seconds(secs as i64)
+}
+
+impl MirrorScheduler {
+    pub fn new() -> Arc<Self> {
+        Arc::new(Self {
+            statuses: Mutex::new(HashMap::new()),
+            registrations: Mutex::new(Registrations::default()),
+            notify: Notify::new(),
+        })
+    }
+
+    /// 在当前 tokio 运行时中启动后台调度循环
+    pub fn start(self: &Arc<Self>) {
+        let config = &app_config().mirror_config;
+        if !config.enabled {
+            info!("镜像后台同步未启用");
+            return;
+        }
+        let tick = Duration::from_secs(config.tick_secs.max(1));
+        let scheduler = self.clone();
+        tokio::spawn(async move {
+            info!("镜像后台同步已启动，检查周期 {:?}", tick);
+            loop {
+                scheduler.run_due().await;
+                tokio::select! {
+                    _ = tokio::time::sleep(tick) => {}
+                    _ = scheduler.notify.notified() => {}
+                }
+            }
+        });
+    }
+
+    /// 所有镜像的状态
+    pub fn statuses(&self) -> Vec<MirrorStatus> {
+        let mut statuses: Vec<MirrorStatus> =
+            self.statuses.lock().unwrap().values().cloned().collect();
+        statuses.sort_by(|a, b| a.repo.cmp(&b.repo));
+        statuses
+    }
+
+    /// 单个镜像的状态
+    pub fn status(&self, repo_name: &RepoPath) -> Option<MirrorStatus> {
+        self.statuses.lock().unwrap().get(repo_name.name()).cloned()
+    }
+
+    // 到了重新扫描的时间时从 bare_repos 重新读取所有登记
+    async fn rescan_if_stale(&self) {
+        let rescan = Duration::from_secs(app_config().mirror_config.rescan_secs.max(1));
+        let stale = self
+            .registrations
+            .lock()
+            .unwrap()
+            .scanned_at
+            .is_none_or(|at| at.elapsed() >= rescan);
+        if !stale {
+            return;
+        }
+        match tokio::task::spawn_blocking(mirror_service::registered_mirrors).await {
+            Ok(mirrors) => {
+                let mut registrations = self.registrations.lock().unwrap();
+                registrations.mirrors = mirrors
+                    .into_iter()
+                    .map(|(repo, mirror)| (repo.name().to_string(), (repo, mirror)))
+                    .collect();
+                registrations.scanned_at = Some(Instant::now());
+            }
+            Err(e) => warn!("扫描镜像登记失败: {}", e),
+        }
+    }
+
+    // 执行到期的任务
+    async fn run_due(self: &Arc<Self>) {
+        self.rescan_if_stale().await;
+        let mirrors: Vec<(RepoPath, MirrorConfig)> = self
+            .registrations
+            .lock()
+            .unwrap()
+            .mirrors
+            .values()
+            .cloned()
+            .collect();
+
+        let now = Local::now();
+        let mut due = Vec::new();
+        {
+            let mut statuses = self.statuses.lock().unwrap();
+            statuses.retain(|name, _| mirrors.iter().any(|(repo, _)| repo.name() == name));
+            for (repo_name, mirror) in mirrors {
+                let status = statuses
+                    .entry(repo_name.name().to_string())
+                    // 新登记的镜像在抖动时间内尽快执行
+                    .or_insert_with(|| MirrorStatus::new(&repo_name, &mirror, now + jitter()));
+                status.interval_secs = mirror.interval_secs;
+                if !status.running && status.next_run <= now {
+                    status.running = true;
+                    status.last_run = Some(now);
+                    due.push((repo_name, mirror));
+                }
+            }
+        }
+
+        for (repo_name, mirror) in due {
+            let scheduler = self.clone();
+            tokio::spawn(async move {
+                let name = repo_name.clone();
+                let result = tokio::task::spawn_blocking(move || {
+                    mirror_service::sync_mirror(&name, &mirror).map_err(|e| e.to_string())
+                })
+                .await
+                .unwrap_or_else(|e| Err(format!("同步任务异常退出: {}", e)));
+                scheduler.finish(&repo_name, result);
+            });
+        }
+    }
+
+    // 记录一次同步的结果并安排下一次执行
+    fn finish(&self, repo_name: &RepoPath, result: Result<String, String>) {
+        let mut statuses = self.statuses.lock().unwrap();
+        let Some(status) = statuses.get_mut(repo_name.name()) else {
+            return;
+        };
+        let now = Local::now();
+        status.running = false;
+        let queued = std::mem::take(&mut status.queued);
+        match result {
+            Ok(message) => {
+                info!("镜像 {} 定时同步成功: {}", repo_name, message);
+                status.last_success = Some(now);
+                status.last_result = Some(message);
+                status.consecutive_failures = 0;
+                status.next_run =
+                    now + chrono::Duration::seconds(status.interval_secs as i64) + jitter();
+            }
+            Err(e) => {
+                status.consecutive_failures += 1;
+                let delay = backoff(status.consecutive_failures);
+                warn!(
+                    "镜像 {} 定时同步失败（连续 {} 次），{} 秒后重试: {}",
+                    repo_name,
+                    status.consecutive_failures,
+                    delay.num_seconds(),
+                    e
+                );
+                status.last_error = Some(e);
+                status.last_error_at = Some(now);
+                status.next_run = now + delay + jitter();
+            }
+        }
+        if queued {
+            status.next_run = now;
+            self.notify.notify_one();
+        }
+    }
+
+    /// 立即安排一次同步，正在运行时在本次结束后再执行
+    pub fn trigger(&self, repo_name: &RepoPath, mirror: &MirrorConfig) {
+        let now = Local::now();
+        {
+            let mut statuses = self.statuses.lock().unwrap();
+            let status = statuses
+                .entry(repo_name.name().to_string())
+                .or_insert_with(|| MirrorStatus::new(repo_name, mirror, now));
+            if status.running {
+                status.queued = true;
+            } else {
+                status.next_run = now;
+            }
+        }
+        info!("镜像 {} 已加入立即同步队列", repo_name);
+        self.notify.notify_one();
+    }
+
+    /// 登记信息更新后刷新缓存，并让调度器立即检查
+    pub fn registration_changed(&self, repo_name: &RepoPath, mirror: &MirrorConfig) {
+        {
+            let mut registrations = self.registrations.lock().unwrap();
+            if mirror.enabled {
+                registrations.mirrors.insert(
+                    repo_name.name().to_string(),
+                    (repo_name.clone(), mirror.clone()),
+                );
+            } else {
+                registrations.mirrors.remove(repo_name.name());
+            }
+        }
+        if let Some(status) = self.statuses.lock().unwrap().get_mut(repo_name.name()) {
+            status.interval_secs = mirror.interval_secs;
+        }
+        self.notify.notify_one();
+    }
+}
@@ -0,0 +1,540 @@
+use crate::config::app_config::app_config;
+use crate::repo::repo_path::RepoPath;
+use crate::service::git_service::{self, BARE_REPOS, BareSyncResult, ProgressObserver, TEST_REPOS};
+use crate::service::quota_service::{self, QuotaError};
+use crate::service::{credential_service, push_mirror_service};
+use anyhow::anyhow;
+use git2::{AutotagOption, FetchOptions, FetchPrune, Oid, Repository};
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+use std::cell::Cell;
+use std::collections::{BTreeMap, HashMap};
+use std::error::Error;
+use std::path::Path;
+use std::sync::{Arc, LazyLock, Mutex, PoisonError};
+
+/// 镜像模式下直接抓取到裸仓库的引用
+const MIRROR_REFSPECS: &[&str] = &["+refs/heads/*:refs/heads/*", "+refs/tags/*:refs/tags/*"];
+/// 镜像同步前后对比的引用
+const MIRROR_REF_PREFIXES: &[&str] = &["refs/heads/", "refs/tags/"];
+
+// 镜像登记信息在裸仓库 config 中的键
+const MIRROR_ENABLED: &str = "mirror.enabled";
+const MIRROR_INTERVAL: &str = "mirror.interval";
+const MIRROR_PRUNE: &str = "mirror.prune";
+const MIRROR_FULL: &str = "mirror.full";
+const MIRROR_DIVERGED: &str = "mirror.diverged";
+
+/// 定时同步的最小间隔（秒）
+const MIN_INTERVAL_SECS: u64 = 60;
+
+// 每个仓库的同步锁，后台任务和定时同步拉取同一个仓库时串行执行
+static SYNC_LOCKS: LazyLock<Mutex<HashMap<String, Arc<Mutex<()>>>>> =
+    LazyLock::new(Default::default);
+
+/// 取得仓库的同步锁，拉取和镜像同步在持有期间执行
+pub fn sync_lock(repo_name: &RepoPath) -> Arc<Mutex<()>> {
+    let mut locks = SYNC_LOCKS.lock().unwrap();
+    // 清理已经没有人持有的锁
+    locks.retain(|_, lock| Arc::strong_count(lock) > 1);
+    locks
+        .entry(repo_name.name().to_string())
+        .or_default()
+        .clone()
+}
+
+/// 镜像登记信息，保存在裸仓库 config 的 [mirror] 段
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct MirrorConfig {
+    // 是否参与后台定时同步
+    pub enabled: bool,
+    // 同步间隔（秒）
+    pub interval_secs: u64,
+    // 同步时删除上游已不存在的引用（仅镜像模式）
+    #[serde(default)]
+    pub prune: bool,
+    // true 使用镜像模式抓取全部引用，false 使用 pull_with_credentials 拉取当前分支并同步裸仓库
+    #[serde(default = "default_full")]
+    pub full: bool,
+    // 本地提交与上游分叉时的处理策略（仅非镜像模式）
+    #[serde(default)]
+    pub diverged: DivergencePolicy,
+}
+
+/// 拉取时本地分支与上游分叉的处理策略
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DivergencePolicy {
+    // 丢弃本地提交，重置到上游（强制镜像）
+    Reset,
+    // 创建合并提交
+    Merge,
+    // 把本地提交变基到上游之上
+    Rebase,
+    // 保留本地分支不动，并报告分叉
+    #[default]
+    Keep,
+}
+
+impl DivergencePolicy {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            DivergencePolicy::Reset => "reset",
+            DivergencePolicy::Merge => "merge",
+            DivergencePolicy::Rebase => "rebase",
+            DivergencePolicy::Keep => "keep",
+        }
+    }
+
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "reset" => Some(DivergencePolicy::Reset),
+            "merge" => Some(DivergencePolicy::Merge),
+            "rebase" => Some(DivergencePolicy::Rebase),
+            "keep" => Some(DivergencePolicy::Keep),
+            _ => None,
+        }
+    }
+}
+
+fn default_full() -> bool {
+    true
+}
+
+impl MirrorConfig {
+    pub fn validate(&self) -> Result<(), Box<dyn Error>> {
+        if self.interval_secs < MIN_INTERVAL_SECS {
+            return Err(anyhow!("interval_secs 不能小于 {} 秒", MIN_INTERVAL_SECS).into());
+        }
+        Ok(())
+    }
+}
+
+/// 读取仓库的镜像登记信息，未登记时返回未启用的默认值
+pub fn read_mirror_config(repo: &Repository) -> Result<MirrorConfig, git2::Error> {
+    let config = repo.config()?;
+    Ok(MirrorConfig {
+        enabled: config.get_bool(MIRROR_ENABLED).unwrap_or(false),
+        interval_secs: config
+            .get_i64(MIRROR_INTERVAL)
+            .ok()
+            .and_then(|v| u64::try_from(v).ok())
+            .unwrap_or(app_config().mirror_config.default_interval_secs),
+        prune: config.get_bool(MIRROR_PRUNE).unwrap_or(false),
+        full: config.get_bool(MIRROR_FULL).unwrap_or(true),
+        diverged: config
+            .get_string(MIRROR_DIVERGED)
+            .ok()
+            .and_then(|v| DivergencePolicy::parse(&v))
+            .unwrap_or_default(),
+    })
+}
+
+/// 写入仓库的镜像登记信息
+pub fn write_mirror_config(repo: &Repository, mirror: &MirrorConfig) -> Result<(), Box<dyn Error>> {
+    mirror.validate()?;
+    let mut config = repo.config()?;
+    config.set_bool(MIRROR_ENABLED, mirror.enabled)?;
+    config.set_i64(MIRROR_INTERVAL, mirror.interval_secs as i64)?;
+    config.set_bool(MIRROR_PRUNE, mirror.prune)?;
+    config.set_bool(MIRROR_FULL, mirror.full)?;
+    config.set_str(MIRROR_DIVERGED, mirror.diverged.as_str())?;
+    info!("镜像登记已更新 {}: {:?}", repo.path().display(), mirror);
+    Ok(())
+}
+
+/// 扫描 bare_repos，返回所有已启用定时同步的镜像
+///
+/// 支持 `name.git` 和 `namespace/name.git` 两种布局。
+pub fn registered_mirrors() -> Vec<(RepoPath, MirrorConfig)> {
+    let mut mirrors = Vec::new();
+    for entry in walkdir::WalkDir::new(BARE_REPOS)
+        .min_depth(1)
+        .max_depth(2)
+        .into_iter()
+        .filter_map(|e| e.ok())
+    {
+        let path = entry.path();
+        if !entry.file_type().is_dir() || path.extension().is_none_or(|ext| ext != "git") {
+            continue;
+        }
+        let Some(rel) = path
+            .strip_prefix(BARE_REPOS)
+            .ok()
+            .and_then(|rel| rel.to_str())
+        else {
+            continue;
+        };
+        let Ok(repo_name) = RepoPath::parse(rel) else {
+            continue;
+        };
+        match Repository::open_bare(path).and_then(|repo| read_mirror_config(&repo)) {
+            Ok(mirror) if mirror.enabled => mirrors.push((repo_name, mirror)),
+            Ok(_) => {}
+            Err(e) => warn!("读取镜像登记失败 {}: {}", path.display(), e),
+        }
+    }
+    mirrors
+}
+
+/// 按登记信息执行一次同步，返回结果摘要
+///
+/// 镜像模式复用 `mirror_with_credentials`，否则复用 `pull_with_credentials`（内部会调用 `sync_bare_repo`），
+/// 分叉后保留本地提交、合并冲突或裸仓库同步失败时视为同步失败，以便在镜像状态中告警。
+pub fn sync_mirror(repo_name: &RepoPath, mirror: &MirrorConfig) -> Result<String, Box<dyn Error>> {
+    if mirror.full {
+        let report = mirror_with_credentials(repo_name, mirror.prune, None)?;
+        if let Some(warning) = report.warning {
+            return Err(anyhow!("{}", warning).into());
+        }
+        Ok(format!(
+            "新增 {}，更新 {}，删除 {}",
+            report.created.len(),
+            report.updated.len(),
+            report.deleted.len()
+        ))
+    } else {
+        let outcome = git_service::pull_with_credentials(repo_name, None)?;
+        if outcome.needs_attention() {
+            return Err(anyhow!("{}", outcome.message).into());
+        }
+        if let BareSyncResult::Failed { error } = &outcome.bare_sync {
+            return Err(anyhow!("同步裸仓库失败: {}", error).into());
+        }
+        Ok(format!(
+            "{}，并入 {} 个提交",
+            outcome.message, outcome.commits_pulled
+        ))
+    }
+}
+
+/// 单个引用的变化，创建时 old 为空，删除时 new 为空
+#[derive(Debug, Clone, Serialize)]
+pub struct RefChange {
+    pub name: String,
+    pub old: Option<String>,
+    pub new: Option<String>,
+}
+
+/// 一次镜像同步的结果
+#[derive(Debug, Default, Clone, Serialize)]
+pub struct MirrorReport {
+    pub repo: String,
+    pub created: Vec<RefChange>,
+    pub updated: Vec<RefChange>,
+    pub deleted: Vec<RefChange>,
+    // 工作仓库同步引用失败时的说明，裸仓库此时已经更新
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub warning: Option<String>,
+}
+
+impl MirrorReport {
+    /// 比较同步前后的引用快照
+    fn diff(
+        repo: String,
+        before: &BTreeMap<String, String>,
+        after: &BTreeMap<String, String>,
+    ) -> Self {
+        let mut report = MirrorReport {
+            repo,
+            ..Default::default()
+        };
+        for change in ref_changes(before, after) {
+            match (&change.old, &change.new) {
+                (None, _) => report.created.push(change),
+                (_, None) => report.deleted.push(change),
+                _ => report.updated.push(change),
+            }
+        }
+        report
+    }
+}
+
+/// 比较两次引用快照，返回新增、更新和删除的引用
+pub fn ref_changes(
+    before: &BTreeMap<String, String>,
+    after: &BTreeMap<String, String>,
+) -> Vec<RefChange> {
+    let mut changes = Vec::new();
+    for (name, new) in after {
+        match before.get(name) {
+            Some(old) if old == new => {}
+            old => changes.push(RefChange {
+                name: name.clone(),
+                old: old.cloned(),
+                new: Some(new.clone()),
+            }),
+        }
+    }
+    for (name, old) in before {
+        if !after.contains_key(name) {
+            changes.push(RefChange {
+                name: name.clone(),
+                old: Some(old.clone()),
+                new: None,
+            });
+        }
+    }
+    changes
+}
+
+/// 记录仓库中指定前缀下的引用当前指向的对象
+pub fn snapshot_refs(
+    repo: &Repository,
+    prefixes: &[&str],
+) -> Result<BTreeMap<String, String>, git2::Error> {
+    let mut refs = BTreeMap::new();
+    for reference in repo.references()? {
+        let reference = reference?;
+        let Some(name) = reference.name() else {
+            continue;
+        };
+        if !prefixes.iter().any(|prefix| name.starts_with(prefix)) {
+            continue;
+        }
+        if let Some(oid) = reference.target() {
+            refs.insert(name.to_string(), oid.to_string());
+        }
+    }
+    Ok(refs)
+}
+
+/// 获取上游地址：优先使用裸仓库的 origin，没有时取工作仓库的 origin
+fn upstream_url(bare: &Repository, repo_name: &RepoPath) -> Result<String, Box<dyn Error>> {
+    if let Ok(remote) = bare.find_remote("origin")
+        && let Some(url) = remote.url()
+    {
+        return Ok(url.to_string());
+    }
+    let work_path = repo_name.work_dir(Path::new(TEST_REPOS))?;
+    let work = Repository::open(&work_path)
+        .map_err(|_| anyhow!("仓库 {} 没有配置上游 origin", repo_name))?;
+    let remote = work.find_remote("origin")?;
+    let url = remote
+        .url()
+        .ok_or_else(|| anyhow!("仓库 {} 的 origin 地址无效", repo_name))?;
+    bare.remote("origin", url)?;
+    info!("为裸仓库 {} 添加上游 origin: {}", repo_name, url);
+    Ok(url.to_string())
+}
+
+/// 查询镜像的上游地址，只读，不会为裸仓库补充 origin
+pub fn upstream_of(repo_name: &RepoPath) -> Option<String> {
+    let origin_url = |repo: Repository| {
+        repo.find_remote("origin")
+            .ok()
+            .and_then(|remote| remote.url().map(String::from))
+    };
+    let bare_path = repo_name.bare_dir(Path::new(BARE_REPOS)).ok()?;
+    if let Some(url) = Repository::open_bare(bare_path).ok().and_then(origin_url) {
+        return Some(url);
+    }
+    let work_path = repo_name.work_dir(Path::new(TEST_REPOS)).ok()?;
+    Repository::open(work_path).ok().and_then(origin_url)
+}
+
+/// 镜像同步：把上游所有分支和标签直接抓取到裸仓库
+///
+/// 与 `pull_with_credentials` 只拉取当前分支不同，这里使用 `+refs/heads/*` 和 `+refs/tags/*`
+/// 强制更新裸仓库中的引用；`prune` 为 true 时删除上游已不存在的引用。
+pub fn mirror_with_credentials(
+    repo_name: &RepoPath,
+    prune: bool,
+    progress: Option<&dyn ProgressObserver>,
+) -> Result<MirrorReport, Box<dyn Error>> {
+    // 同一仓库的拉取和镜像同步串行执行，锁中没有数据，中毒后照常使用
+    let lock = sync_lock(repo_name);
+    let _guard = lock.lock().unwrap_or_else(PoisonError::into_inner);
+    let bare_path = repo_name.bare_dir(Path::new(BARE_REPOS))?;
+    let bare = Repository::open_bare(&bare_path)
+        .map_err(|e| anyhow!("打开裸仓库 {} 失败: {}", bare_path.display(), e))?;
+    let url = upstream_url(&bare, repo_name)?;
+    info!("开始镜像同步 {} <- {}", repo_name, url);
+
+    let before = snapshot_refs(&bare, MIRROR_REF_PREFIXES)?;
+
+    // 与克隆一样受配额限制
+    quota_service::check_quota(repo_name, 0)?;
+    let remaining = quota_service::remaining_bytes(repo_name)?;
+    let received = Cell::new(0u64);
+
+    let mut callbacks = credential_service::remote_callbacks(&url, Some(repo_name))?;
+    callbacks.transfer_progress(|stats| {
+        received.set(stats.received_bytes() as u64);
+        remaining.is_none_or(|limit| received.get() <= limit)
+            && progress.is_none_or(|progress| progress.transfer(&stats))
+    });
+    git_service::observe_sideband(&mut callbacks, progress);
+
+    let mut fetch_options = FetchOptions::new();
+    fetch_options
+        .remote_callbacks(callbacks)
+        .download_tags(AutotagOption::None)
+        .prune(if prune {
+            FetchPrune::On
+        } else {
+            FetchPrune::Off
+        });
+
+    let mut remote = bare.remote_anonymous(&url)?;
+    if let Err(e) = remote.fetch(MIRROR_REFSPECS, Some(&mut fetch_options), None) {
+        if let Some(limit) = remaining.filter(|limit| received.get() > *limit) {
+            return Err(QuotaError::TransferTooLarge {
+                received: received.get(),
+                remaining: limit,
+            }
+            .into());
+        }
+        warn!("镜像同步 {} 失败: {}", repo_name, e);
+        return Err(e.into());
+    }
+
+    let after = snapshot_refs(&bare, MIRROR_REF_PREFIXES)?;
+    let mut report = MirrorReport::diff(repo_name.to_string(), &before, &after);
+    if before != after {
+        push_mirror_service::schedule(repo_name);
+        // 裸仓库的引用被强制更新，工作仓库要跟上，否则之后普通拉取同步裸仓库时会被拒绝
+        report.warning = sync_work_refs(repo_name, &report);
+    }
+    info!(
+        "镜像同步 {} 完成: 新增 {}，更新 {}，删除 {}",
+        repo_name,
+        report.created.len(),
+        report.updated.len(),
+        report.deleted.len()
+    );
+    Ok(report)
+}
+
+// 把镜像同步的引用变化逐个同步到工作仓库，返回失败的说明
+fn sync_work_refs(repo_name: &RepoPath, report: &MirrorReport) -> Option<String> {
+    let mut failures = Vec::new();
+    for change in report
+        .created
+        .iter()
+        .chain(&report.updated)
+        .chain(&report.deleted)
+    {
+        let result = match change.new.as_deref().map(Oid::from_str).transpose() {
+            Ok(target) => git_service::update_work_ref(repo_name, &change.name, target, true),
+            Err(e) => Err(e.into()),
+        };
+        if let Err(e) = result {
+            warn!("工作仓库同步引用 {} 失败: {}", change.name, e);
+            failures.push(format!("{}: {}", change.name, e));
+        }
+    }
+    (!failures.is_empty()).then(|| format!("工作仓库同步引用失败: {}", failures.join("; ")))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sync_lock_is_shared_per_repo() {
+        let a = RepoPath::parse("lock-test/a").unwrap();
+        let b = RepoPath::parse("lock-test/b").unwrap();
+        let lock = sync_lock(&a);
+        assert!(Arc::ptr_eq(&lock, &sync_lock(&a)));
+        assert!(!Arc::ptr_eq(&lock, &sync_lock(&b)));
+
+        let _guard = lock.lock().unwrap();
+        assert!(sync_lock(&a).try_lock().is_err());
+        assert!(sync_lock(&b).try_lock().is_ok());
+    }
+
+    // 在上游仓库的 refname 上提交一个文件，parent 为 None 时创建没有父提交的新历史
+    fn commit_file(repo: &Repository, refname: &str, content: &str, parent: Option<Oid>) -> Oid {
+        let signature = git2::Signature::now("test", "test@localhost").unwrap();
+        let blob = repo.blob(content.as_bytes()).unwrap();
+        let mut builder = repo.treebuilder(None).unwrap();
+        builder.insert("file.txt", blob, 0o100644).unwrap();
+        let tree = repo.find_tree(builder.write().unwrap()).unwrap();
+        let parents: Vec<_> = parent
+            .map(|p| repo.find_commit(p).unwrap())
+            .into_iter()
+            .collect();
+        let parents: Vec<_> = parents.iter().collect();
+        let oid = repo
+            .commit(None, &signature, &signature, content, &tree, &parents)
+            .unwrap();
+        repo.reference(refname, oid, true, content).unwrap();
+        oid
+    }
+
+    // 测试结束后删除工作仓库和裸仓库，以及因此变空的上级目录
+    struct Cleanup(Vec<std::path::PathBuf>);
+
+    impl Drop for Cleanup {
+        fn drop(&mut self) {
+            for path in &self.0 {
+                let _ = std::fs::remove_dir_all(path);
+                for parent in path.ancestors().skip(1) {
+                    if parent.as_os_str().is_empty() || std::fs::remove_dir(parent).is_err() {
+                        break;
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn pull_after_mirror_sync_updates_the_bare_repo() {
+        let dir = tempfile::tempdir().unwrap();
+        let upstream = Repository::init(dir.path()).unwrap();
+        let main = commit_file(&upstream, "refs/heads/main", "main 1", None);
+        upstream.set_head("refs/heads/main").unwrap();
+        let feature = commit_file(&upstream, "refs/heads/feature", "feature 1", Some(main));
+        upstream
+            .reference("refs/tags/v1", feature, true, "v1")
+            .unwrap();
+
+        let repo_name = RepoPath::parse("mirror-test/pull-after-mirror").unwrap();
+        let work_path = repo_name.work_dir(Path::new(TEST_REPOS)).unwrap();
+        let bare_path = repo_name.bare_dir(Path::new(BARE_REPOS)).unwrap();
+        let _cleanup = Cleanup(vec![work_path.clone(), bare_path.clone()]);
+        let url = format!("file://{}", dir.path().display());
+        git_service::clone_with_credentials(&url, &repo_name, None).unwrap();
+        // 克隆只在工作仓库中建立检出的分支，其余分支由第一次镜像同步建立
+        let report = mirror_with_credentials(&repo_name, true, None).unwrap();
+        assert!(report.warning.is_none(), "{:?}", report.warning);
+
+        // 上游改写 feature、推进 main、移动标签并删除一个分支后再次镜像同步
+        let main = commit_file(&upstream, "refs/heads/main", "main 2", Some(main));
+        let feature = commit_file(&upstream, "refs/heads/feature", "rewritten", None);
+        upstream
+            .reference("refs/tags/v1", main, true, "v1")
+            .unwrap();
+        commit_file(&upstream, "refs/heads/gone", "gone", Some(main));
+        mirror_with_credentials(&repo_name, true, None).unwrap();
+        upstream
+            .find_reference("refs/heads/gone")
+            .unwrap()
+            .delete()
+            .unwrap();
+        let report = mirror_with_credentials(&repo_name, true, None).unwrap();
+        assert!(report.warning.is_none(), "{:?}", report.warning);
+
+        let work = Repository::open(&work_path).unwrap();
+        assert_eq!(work.refname_to_id("refs/heads/main").unwrap(), main);
+        assert_eq!(work.refname_to_id("refs/heads/feature").unwrap(), feature);
+        assert_eq!(work.refname_to_id("refs/tags/v1").unwrap(), main);
+        assert!(work.find_reference("refs/heads/gone").is_err());
+        assert!(work.statuses(None).unwrap().is_empty());
+
+        // 之后的普通拉取把新提交同步到裸仓库，不会因为工作仓库中的旧引用被拒绝
+        let main = commit_file(&upstream, "refs/heads/main", "main 3", Some(main));
+        let outcome = git_service::pull_with_credentials(&repo_name, None).unwrap();
+        assert!(
+            matches!(outcome.bare_sync, BareSyncResult::Succeeded),
+            "{:?}",
+            outcome.bare_sync
+        );
+        let bare = Repository::open_bare(&bare_path).unwrap();
+        assert_eq!(bare.refname_to_id("refs/heads/main").unwrap(), main);
+        assert_eq!(bare.refname_to_id("refs/heads/feature").unwrap(), feature);
+        assert!(bare.find_reference("refs/heads/gone").is_err());
+    }
+}
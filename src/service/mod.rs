@@ -1 +1,14 @@
+pub mod browse_service;
+pub mod commit_service;
+pub mod credential_service;
 pub mod git_service;
+pub mod job_service;
+pub mod mirror_scheduler;
+pub mod mirror_service;
+pub mod push_mirror_service;
+pub mod quota_service;
+pub mod ref_service;
+pub mod settings_service;
+pub mod ssh_service;
+pub mod template_service;
+pub mod webhook_service;
@@ -0,0 +1,221 @@
+use crate::config::app_config::app_config;
+use crate::repo::repo_path::RepoPath;
+use crate::service::git_service::BARE_REPOS;
+use actix_web::{HttpResponse, ResponseError};
+use log::{info, warn};
+use serde::Serialize;
+use std::path::Path;
+use thiserror::Error;
+use walkdir::WalkDir;
+
+const MB: u64 = 1024 * 1024;
+
+#[derive(Debug, Error)]
+pub enum QuotaError {
+    #[error("超出{scope}磁盘配额: 已用 {used} 字节，本次新增约 {incoming} 字节，上限 {limit} 字节")]
+    Exceeded {
+        scope: String,
+        used: u64,
+        incoming: u64,
+        limit: u64,
+    },
+    #[error("传输数据超出剩余配额: 已接收 {received} 字节，剩余 {remaining} 字节")]
+    TransferTooLarge { received: u64, remaining: u64 },
+    #[error("统计磁盘用量失败: {0}")]
+    Io(String),
+}
+
+impl ResponseError for QuotaError {
+    fn status_code(&self) -> actix_web::http::StatusCode {
+        match self {
+            QuotaError::Exceeded { .. } | QuotaError::TransferTooLarge { .. } => {
+                actix_web::http::StatusCode::PAYLOAD_TOO_LARGE
+            }
+            QuotaError::Io(_) => actix_web::http::StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        HttpResponse::build(self.status_code()).body(self.to_string())
+    }
+}
+
+/// 裸仓库对象目录的用量，字段含义与 `git count-objects -v` 一致
+#[derive(Debug, Default, Serialize)]
+pub struct ObjectUsage {
+    pub loose_objects: u64,
+    pub loose_bytes: u64,
+    pub packs: u64,
+    pub pack_bytes: u64,
+    pub total_bytes: u64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct NamespaceUsage {
+    pub namespace: String,
+    pub used_bytes: u64,
+    pub limit_bytes: Option<u64>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct RepoUsage {
+    pub repo: String,
+    pub objects: ObjectUsage,
+    pub limit_bytes: Option<u64>,
+    pub namespace: Option<NamespaceUsage>,
+}
+
+/// 遍历裸仓库的 objects 目录统计用量，仓库不存在时返回 0
+pub fn object_usage(bare_path: &Path) -> Result<ObjectUsage, QuotaError> {
+    let mut usage = ObjectUsage::default();
+    let objects = bare_path.join("objects");
+    if !objects.exists() {
+        return Ok(usage);
+    }
+
+    for entry in WalkDir::new(&objects) {
+        let entry = entry.map_err(|e| QuotaError::Io(e.to_string()))?;
+        if !entry.file_type().is_file() {
+            continue;
+        }
+        let size = entry
+            .metadata()
+            .map_err(|e| QuotaError::Io(e.to_string()))?
+            .len();
+        let rel = entry.path().strip_prefix(&objects).unwrap_or(entry.path());
+        let top = rel
+            .components()
+            .next()
+            .and_then(|c| c.as_os_str().to_str())
+            .unwrap_or("");
+
+        if top == "pack" {
+            if entry.path().extension().is_some_and(|ext| ext == "pack") {
+                usage.packs += 1;
+            }
+            usage.pack_bytes += size;
+        } else if top.len() == 2 && top.chars().all(|c| c.is_ascii_hexdigit()) {
+            usage.loose_objects += 1;
+            usage.loose_bytes += size;
+        }
+        usage.total_bytes += size;
+    }
+    Ok(usage)
+}
+
+/// 统计命名空间下所有裸仓库的对象用量
+fn namespace_used(namespace: &str) -> Result<u64, QuotaError> {
+    let ns_path = Path::new(BARE_REPOS).join(namespace);
+    if !ns_path.exists() {
+        return Ok(0);
+    }
+    let mut used = 0;
+    for entry in std::fs::read_dir(&ns_path).map_err(|e| QuotaError::Io(e.to_string()))? {
+        let entry = entry.map_err(|e| QuotaError::Io(e.to_string()))?;
+        if entry.path().is_dir() {
+            used += object_usage(&entry.path())?.total_bytes;
+        }
+    }
+    Ok(used)
+}
+
+fn limit_bytes(specific: Option<&u64>, default_mb: u64) -> Option<u64> {
+    match specific.copied().unwrap_or(default_mb) {
+        0 => None,
+        mb => Some(mb * MB),
+    }
+}
+
+fn repo_limit(repo_name: &RepoPath) -> Option<u64> {
+    let quota = &app_config().quota_config;
+    limit_bytes(quota.repos.get(repo_name.name()), quota.repo_limit_mb)
+}
+
+fn namespace_limit(namespace: &str) -> Option<u64> {
+    let quota = &app_config().quota_config;
+    limit_bytes(quota.namespaces.get(namespace), quota.namespace_limit_mb)
+}
+
+/// 查询仓库及其命名空间的当前用量和上限
+pub fn repo_usage(repo_name: &RepoPath) -> Result<RepoUsage, Box<dyn std::error::Error>> {
+    let bare_path = repo_name.bare_dir(Path::new(BARE_REPOS))?;
+    let objects = object_usage(&bare_path)?;
+    let namespace = match repo_name.namespace() {
+        Some(ns) => Some(NamespaceUsage {
+            namespace: ns.to_string(),
+            used_bytes: namespace_used(ns)?,
+            limit_bytes: namespace_limit(ns),
+        }),
+        None => None,
+    };
+    Ok(RepoUsage {
+        repo: repo_name.to_string(),
+        objects,
+        limit_bytes: repo_limit(repo_name),
+        namespace,
+    })
+}
+
+/// 剩余可用字节数，取仓库和命名空间两者中较小的那个；`None` 表示不限制
+pub fn remaining_bytes(repo_name: &RepoPath) -> Result<Option<u64>, QuotaError> {
+    let bare_path = repo_name
+        .bare_dir(Path::new(BARE_REPOS))
+        .map_err(|e| QuotaError::Io(e.to_string()))?;
+    let mut remaining: Option<u64> = None;
+
+    if let Some(limit) = repo_limit(repo_name) {
+        let used = object_usage(&bare_path)?.total_bytes;
+        remaining = Some(limit.saturating_sub(used));
+    }
+    if let Some(ns) = repo_name.namespace()
+        && let Some(limit) = namespace_limit(ns)
+    {
+        let left = limit.saturating_sub(namespace_used(ns)?);
+        remaining = Some(remaining.map_or(left, |r| r.min(left)));
+    }
+    Ok(remaining)
+}
+
+/// 检查写入 `incoming` 字节后是否会超出仓库或命名空间配额
+pub fn check_quota(repo_name: &RepoPath, incoming: u64) -> Result<(), QuotaError> {
+    let bare_path = repo_name
+        .bare_dir(Path::new(BARE_REPOS))
+        .map_err(|e| QuotaError::Io(e.to_string()))?;
+
+    if let Some(limit) = repo_limit(repo_name) {
+        let used = object_usage(&bare_path)?.total_bytes;
+        if used.saturating_add(incoming) > limit {
+            warn!(
+                "仓库 {} 超出配额: {} + {} > {}",
+                repo_name, used, incoming, limit
+            );
+            return Err(QuotaError::Exceeded {
+                scope: format!("仓库 {} ", repo_name),
+                used,
+                incoming,
+                limit,
+            });
+        }
+    }
+
+    if let Some(ns) = repo_name.namespace()
+        && let Some(limit) = namespace_limit(ns)
+    {
+        let used = namespace_used(ns)?;
+        if used.saturating_add(incoming) > limit {
+            warn!(
+                "命名空间 {} 超出配额: {} + {} > {}",
+                ns, used, incoming, limit
+            );
+            return Err(QuotaError::Exceeded {
+                scope: format!("命名空间 {} ", ns),
+                used,
+                incoming,
+                limit,
+            });
+        }
+    }
+
+    info!("仓库 {} 配额检查通过，新增约 {} 字节", repo_name, incoming);
+    Ok(())
+}
@@ -0,0 +1,265 @@
+use crate::service::browse_service::{self, CommitSummary, Person};
+use crate::service::settings_service;
+use actix_web::{HttpResponse, ResponseError};
+use git2::{BranchType, Commit, Oid, Repository};
+use log::info;
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub enum RefError {
+    #[error("请求非法: {0}")]
+    Invalid(String),
+    #[error("{0}")]
+    NotFound(String),
+    #[error("{0}")]
+    Conflict(String),
+    #[error("{0}")]
+    Forbidden(String),
+    #[error("操作仓库失败: {0}")]
+    Git(#[from] git2::Error),
+}
+
+impl ResponseError for RefError {
+    fn status_code(&self) -> actix_web::http::StatusCode {
+        match self {
+            RefError::Invalid(_) => actix_web::http::StatusCode::BAD_REQUEST,
+            RefError::NotFound(_) => actix_web::http::StatusCode::NOT_FOUND,
+            RefError::Conflict(_) => actix_web::http::StatusCode::CONFLICT,
+            RefError::Forbidden(_) => actix_web::http::StatusCode::FORBIDDEN,
+            RefError::Git(_) => actix_web::http::StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        HttpResponse::build(self.status_code()).body(self.to_string())
+    }
+}
+
+/// 创建分支的请求体
+#[derive(Debug, Deserialize)]
+pub struct CreateBranch {
+    pub name: String,
+    // 起点的分支、标签或提交，默认为默认分支
+    pub from: Option<String>,
+}
+
+/// 创建标签的请求体，带 message 时创建附注标签，否则为轻量标签
+#[derive(Debug, Deserialize)]
+pub struct CreateTag {
+    pub name: String,
+    // 标签指向的分支、标签或提交，默认为默认分支
+    pub target: Option<String>,
+    pub message: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct BranchRef {
+    pub name: String,
+    pub target: String,
+    pub commit: CommitSummary,
+}
+
+#[derive(Debug, Serialize)]
+pub struct TagRef {
+    pub name: String,
+    // 引用本身指向的对象，附注标签时是标签对象
+    pub target: String,
+    pub commit: CommitSummary,
+    pub annotated: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tagger: Option<Person>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub message: Option<String>,
+}
+
+fn resolve<'r>(repo: &'r Repository, rev: Option<&str>) -> Result<Commit<'r>, RefError> {
+    let rev = rev.filter(|rev| !rev.is_empty()).unwrap_or("HEAD");
+    browse_service::resolve_commit(repo, rev)
+        .ok_or_else(|| RefError::NotFound(format!("版本 {} 不存在", rev)))
+}
+
+// receive.denyDeletes 同时约束推送和接口，读不到设置时同样拒绝
+fn ensure_deletes_allowed(repo: &Repository) -> Result<(), RefError> {
+    let settings = settings_service::read_settings(repo)
+        .map_err(|e| RefError::Forbidden(format!("无法读取仓库设置，拒绝删除: {}", e)))?;
+    if !settings.allow_deletes {
+        return Err(RefError::Forbidden(
+            "仓库设置不允许删除分支和标签".to_string(),
+        ));
+    }
+    Ok(())
+}
+
+/// 在裸仓库中创建分支，已存在时返回冲突
+pub fn create_branch(repo: &Repository, request: &CreateBranch) -> Result<BranchRef, RefError> {
+    let name = request.name.as_str();
+    if !git2::Branch::name_is_valid(name)? {
+        return Err(RefError::Invalid(format!("分支名 {} 非法", name)));
+    }
+    if repo.find_branch(name, BranchType::Local).is_ok() {
+        return Err(RefError::Conflict(format!("分支 {} 已存在", name)));
+    }
+    let commit = resolve(repo, request.from.as_deref())?;
+    repo.branch(name, &commit, false)
+        .map_err(|e| match e.code() {
+            git2::ErrorCode::Exists => RefError::Conflict(format!("分支 {} 已存在", name)),
+            _ => RefError::Git(e),
+        })?;
+    info!("创建分支 {} -> {}", name, commit.id());
+    Ok(BranchRef {
+        name: name.to_string(),
+        target: commit.id().to_string(),
+        commit: CommitSummary::from_commit(&commit),
+    })
+}
+
+/// 删除分支，默认分支和受保护分支不能删除
+pub fn delete_branch(repo: &Repository, name: &str) -> Result<(), RefError> {
+    let mut branch = repo
+        .find_branch(name, BranchType::Local)
+        .map_err(|_| RefError::NotFound(format!("分支 {} 不存在", name)))?;
+    let refname = format!("refs/heads/{}", name);
+    if repo
+        .find_reference("HEAD")
+        .ok()
+        .and_then(|head| head.symbolic_target().map(str::to_string))
+        == Some(refname)
+    {
+        return Err(RefError::Forbidden(format!("默认分支 {} 不能删除", name)));
+    }
+    if settings_service::is_protected_branch(repo, name)? {
+        return Err(RefError::Forbidden(format!(
+            "分支 {} 受保护，不能删除",
+            name
+        )));
+    }
+    ensure_deletes_allowed(repo)?;
+    branch.delete()?;
+    info!("删除分支 {}", name);
+    Ok(())
+}
+
+/// 创建标签，附注标签的标签者为调用者
+pub fn create_tag(
+    repo: &Repository,
+    request: &CreateTag,
+    tagger: &git2::Signature,
+) -> Result<TagRef, RefError> {
+    let name = request.name.as_str();
+    if !git2::Tag::is_valid_name(name) {
+        return Err(RefError::Invalid(format!("标签名 {} 非法", name)));
+    }
+    if repo.find_reference(&format!("refs/tags/{}", name)).is_ok() {
+        return Err(RefError::Conflict(format!("标签 {} 已存在", name)));
+    }
+    let commit = resolve(repo, request.target.as_deref())?;
+    let result = match request.message.as_deref() {
+        Some(message) if !message.trim().is_empty() => {
+            repo.tag(name, commit.as_object(), tagger, message, false)
+        }
+        _ => repo.tag_lightweight(name, commit.as_object(), false),
+    };
+    let oid = result.map_err(|e| match e.code() {
+        git2::ErrorCode::Exists => RefError::Conflict(format!("标签 {} 已存在", name)),
+        _ => RefError::Git(e),
+    })?;
+    info!("创建标签 {} -> {}", name, commit.id());
+    Ok(tag_ref(repo, name, oid, &commit))
+}
+
+fn tag_ref(repo: &Repository, name: &str, oid: Oid, commit: &Commit) -> TagRef {
+    let tag = repo.find_tag(oid).ok();
+    TagRef {
+        name: name.to_string(),
+        target: oid.to_string(),
+        commit: CommitSummary::from_commit(commit),
+        annotated: tag.is_some(),
+        tagger: tag
+            .as_ref()
+            .and_then(|tag| tag.tagger())
+            .map(|tagger| Person::from_signature(&tagger)),
+        message: tag
+            .as_ref()
+            .and_then(|tag| tag.message())
+            .map(str::to_string),
+    }
+}
+
+/// 删除标签
+pub fn delete_tag(repo: &Repository, name: &str) -> Result<(), RefError> {
+    if repo.find_reference(&format!("refs/tags/{}", name)).is_err() {
+        return Err(RefError::NotFound(format!("标签 {} 不存在", name)));
+    }
+    ensure_deletes_allowed(repo)?;
+    repo.tag_delete(name)?;
+    info!("删除标签 {}", name);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::Path;
+
+    fn repo_with_branches(path: &Path) -> Repository {
+        let repo = Repository::init_bare(path).unwrap();
+        let signature = git2::Signature::now("test", "test@localhost").unwrap();
+        let tree_id = repo.treebuilder(None).unwrap().write().unwrap();
+        let tree = repo.find_tree(tree_id).unwrap();
+        let oid = repo
+            .commit(
+                Some("refs/heads/main"),
+                &signature,
+                &signature,
+                "init",
+                &tree,
+                &[],
+            )
+            .unwrap();
+        drop(tree);
+        let commit = repo.find_commit(oid).unwrap();
+        repo.branch("release/1.0", &commit, false).unwrap();
+        repo.branch("feature", &commit, false).unwrap();
+        repo.tag_lightweight("v1", commit.as_object(), false)
+            .unwrap();
+        drop(commit);
+        repo
+    }
+
+    #[test]
+    fn protected_branches_cannot_be_deleted() {
+        let dir = tempfile::tempdir().unwrap();
+        let repo = repo_with_branches(dir.path());
+        repo.config()
+            .unwrap()
+            .set_multivar("protection.branch", "^$", "release/*")
+            .unwrap();
+        assert!(matches!(
+            delete_branch(&repo, "release/1.0"),
+            Err(RefError::Forbidden(_))
+        ));
+        delete_branch(&repo, "feature").unwrap();
+    }
+
+    #[test]
+    fn unreadable_settings_reject_deletes() {
+        let dir = tempfile::tempdir().unwrap();
+        let repo = repo_with_branches(dir.path());
+        // 无法解析的值不能当作未配置
+        repo.config()
+            .unwrap()
+            .set_str("receive.denyDeletes", "maybe")
+            .unwrap();
+        assert!(matches!(
+            delete_branch(&repo, "feature"),
+            Err(RefError::Forbidden(_))
+        ));
+        assert!(matches!(
+            delete_tag(&repo, "v1"),
+            Err(RefError::Forbidden(_))
+        ));
+        assert!(repo.find_reference("refs/heads/feature").is_ok());
+        assert!(repo.find_reference("refs/tags/v1").is_ok());
+    }
+}
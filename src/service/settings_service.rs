@@ -0,0 +1,223 @@
+use crate::service::ssh_service;
+use actix_web::{HttpResponse, ResponseError};
+use git2::Repository;
+use log::info;
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+// 各设置项在裸仓库 config 中对应的键
+const DENY_NON_FAST_FORWARDS: &str = "receive.denyNonFastForwards";
+const DENY_DELETES: &str = "receive.denyDeletes";
+const MAX_INPUT_SIZE: &str = "receive.maxInputSize";
+const ALLOW_FILTER: &str = "uploadpack.allowFilter";
+// 部分克隆之后按需补取对象时需要请求未通告的对象
+const ALLOW_REACHABLE_SHA1_IN_WANT: &str = "uploadpack.allowReachableSHA1InWant";
+const DESCRIPTION: &str = "gitweb.description";
+// 受保护分支的名称或通配符，每个一条
+const PROTECTED_BRANCH: &str = "protection.branch";
+
+/// 描述的最大长度（字符）
+const MAX_DESCRIPTION_LEN: usize = 512;
+/// max_object_size 的上限：1 TB
+const MAX_OBJECT_SIZE_LIMIT: u64 = 1 << 40;
+/// 受保护分支规则的最大条数和单条长度
+const MAX_PROTECTED_BRANCHES: usize = 100;
+const MAX_PATTERN_LEN: usize = 255;
+
+#[derive(Debug, Error)]
+pub enum SettingsError {
+    #[error("设置项 {field} 非法: {reason}")]
+    Invalid { field: &'static str, reason: String },
+    #[error("读写仓库配置失败: {0}")]
+    Git(#[from] git2::Error),
+    #[error("写入仓库描述失败: {0}")]
+    Io(#[from] std::io::Error),
+}
+
+impl ResponseError for SettingsError {
+    fn status_code(&self) -> actix_web::http::StatusCode {
+        match self {
+            SettingsError::Invalid { .. } => actix_web::http::StatusCode::BAD_REQUEST,
+            _ => actix_web::http::StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        HttpResponse::build(self.status_code()).body(self.to_string())
+    }
+}
+
+/// 允许通过接口调整的仓库设置，全部保存在裸仓库的 config 中
+#[derive(Debug, Serialize)]
+pub struct RepoSettings {
+    // 是否允许强制推送（receive.denyNonFastForwards 取反）
+    pub allow_force_push: bool,
+    // 是否允许删除分支和标签（receive.denyDeletes 取反）
+    pub allow_deletes: bool,
+    // 单次推送的 pack 大小上限（字节），同时限制了其中单个对象的大小，0 表示不限制
+    // 由 git receive-pack 按 receive.maxInputSize 执行
+    pub max_object_size: u64,
+    // 是否允许部分克隆（uploadpack.allowFilter）
+    pub allow_filter: bool,
+    // 仓库描述（gitweb.description，同时写入 description 文件）
+    pub description: String,
+    // 受保护分支的名称，支持 * 和 ? 通配符
+    // 受保护的分支不能通过接口删除或移动，推送时也不能删除
+    pub protected_branches: Vec<String>,
+}
+
+/// PATCH 请求体，只包含需要修改的字段，出现白名单以外的字段时拒绝
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SettingsPatch {
+    pub allow_force_push: Option<bool>,
+    pub allow_deletes: Option<bool>,
+    pub max_object_size: Option<u64>,
+    pub allow_filter: Option<bool>,
+    pub description: Option<String>,
+    // 整体替换受保护分支列表
+    pub protected_branches: Option<Vec<String>>,
+}
+
+impl SettingsPatch {
+    fn validate(&self) -> Result<(), SettingsError> {
+        if let Some(size) = self.max_object_size
+            && size > MAX_OBJECT_SIZE_LIMIT
+        {
+            return Err(SettingsError::Invalid {
+                field: "max_object_size",
+                reason: format!("不能超过 {} 字节", MAX_OBJECT_SIZE_LIMIT),
+            });
+        }
+        if let Some(description) = &self.description {
+            if description.chars().count() > MAX_DESCRIPTION_LEN {
+                return Err(SettingsError::Invalid {
+                    field: "description",
+                    reason: format!("不能超过 {} 个字符", MAX_DESCRIPTION_LEN),
+                });
+            }
+            if description.chars().any(char::is_control) {
+                return Err(SettingsError::Invalid {
+                    field: "description",
+                    reason: "不能包含换行等控制字符".to_string(),
+                });
+            }
+        }
+        if let Some(patterns) = &self.protected_branches {
+            if patterns.len() > MAX_PROTECTED_BRANCHES {
+                return Err(SettingsError::Invalid {
+                    field: "protected_branches",
+                    reason: format!("不能超过 {} 条", MAX_PROTECTED_BRANCHES),
+                });
+            }
+            if let Some(pattern) = patterns.iter().find(|pattern| {
+                pattern.is_empty()
+                    || pattern.len() > MAX_PATTERN_LEN
+                    || pattern.chars().any(|c| c.is_control() || c.is_whitespace())
+            }) {
+                return Err(SettingsError::Invalid {
+                    field: "protected_branches",
+                    reason: format!("规则 {:?} 非法", pattern),
+                });
+            }
+        }
+        Ok(())
+    }
+}
+
+// 未配置时返回 None，配置文件无法解析等其他错误照常返回
+fn optional<T>(result: Result<T, git2::Error>) -> Result<Option<T>, git2::Error> {
+    match result {
+        Ok(value) => Ok(Some(value)),
+        Err(e) if e.code() == git2::ErrorCode::NotFound => Ok(None),
+        Err(e) => Err(e),
+    }
+}
+
+/// 读取仓库设置，未配置的项使用 git 的默认行为
+///
+/// 配置读取失败时返回错误，不会退回默认值，调用方据此拒绝受限制的操作。
+pub fn read_settings(repo: &Repository) -> Result<RepoSettings, SettingsError> {
+    let config = repo.config()?;
+    let get_bool = |key: &str| optional(config.get_bool(key));
+
+    Ok(RepoSettings {
+        allow_force_push: !get_bool(DENY_NON_FAST_FORWARDS)?.unwrap_or(false),
+        allow_deletes: !get_bool(DENY_DELETES)?.unwrap_or(false),
+        max_object_size: optional(config.get_i64(MAX_INPUT_SIZE))?
+            .and_then(|v| u64::try_from(v).ok())
+            .unwrap_or(0),
+        allow_filter: get_bool(ALLOW_FILTER)?.unwrap_or(false),
+        description: optional(config.get_string(DESCRIPTION))?.unwrap_or_default(),
+        protected_branches: protected_branches(&config)?,
+    })
+}
+
+fn protected_branches(config: &git2::Config) -> Result<Vec<String>, git2::Error> {
+    let mut patterns = Vec::new();
+    let mut entries = match config.multivar(PROTECTED_BRANCH, None) {
+        Ok(entries) => entries,
+        Err(e) if e.code() == git2::ErrorCode::NotFound => return Ok(patterns),
+        Err(e) => return Err(e),
+    };
+    while let Some(entry) = entries.next() {
+        if let Some(pattern) = entry?.value() {
+            patterns.push(pattern.to_string());
+        }
+    }
+    Ok(patterns)
+}
+
+/// 分支（不含 `refs/heads/` 前缀）是否匹配受保护分支规则
+///
+/// 读取配置失败时返回错误，调用方应当拒绝操作而不是当作未受保护。
+pub fn is_protected_branch(repo: &Repository, branch: &str) -> Result<bool, git2::Error> {
+    Ok(protected_branches(&repo.config()?)?
+        .iter()
+        .any(|pattern| ssh_service::glob_match(pattern, branch)))
+}
+
+/// 校验并写入设置，返回更新后的完整设置
+pub fn update_settings(
+    repo: &Repository,
+    patch: &SettingsPatch,
+) -> Result<RepoSettings, SettingsError> {
+    patch.validate()?;
+    let mut config = repo.config()?;
+
+    if let Some(allow) = patch.allow_force_push {
+        config.set_bool(DENY_NON_FAST_FORWARDS, !allow)?;
+    }
+    if let Some(allow) = patch.allow_deletes {
+        config.set_bool(DENY_DELETES, !allow)?;
+    }
+    if let Some(size) = patch.max_object_size {
+        if size == 0 {
+            // 不存在时 remove 会报错，忽略即可
+            let _ = config.remove(MAX_INPUT_SIZE);
+        } else {
+            config.set_i64(MAX_INPUT_SIZE, size as i64)?;
+        }
+    }
+    if let Some(allow) = patch.allow_filter {
+        config.set_bool(ALLOW_FILTER, allow)?;
+        config.set_bool(ALLOW_REACHABLE_SHA1_IN_WANT, allow)?;
+    }
+    if let Some(description) = &patch.description {
+        config.set_str(DESCRIPTION, description)?;
+        std::fs::write(
+            repo.path().join("description"),
+            format!("{}\n", description),
+        )?;
+    }
+    if let Some(patterns) = &patch.protected_branches {
+        // 没有旧规则时 remove_multivar 会报错，忽略即可
+        let _ = config.remove_multivar(PROTECTED_BRANCH, ".*");
+        for pattern in patterns {
+            config.set_multivar(PROTECTED_BRANCH, "^$", pattern)?;
+        }
+    }
+
+    info!("仓库 {} 设置已更新: {:?}", repo.path().display(), patch);
+    read_settings(repo)
+}
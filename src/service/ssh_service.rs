@@ -0,0 +1,478 @@
+use crate::config::app_config::{HostKeyPolicy, app_config};
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD as BASE64_STANDARD;
+use git2::CertificateCheckStatus;
+use git2::cert::Cert;
+use hmac::{Hmac, Mac};
+use log::{info, warn};
+use sha1::Sha1;
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+/// SSH 默认端口
+const DEFAULT_SSH_PORT: u16 = 22;
+
+// 追加 known_hosts 时需要串行
+static KNOWN_HOSTS_LOCK: Mutex<()> = Mutex::new(());
+
+/// 主机密钥在 known_hosts 中的查找结果
+#[derive(Debug, PartialEq)]
+pub enum HostKeyStatus {
+    // 存在同类型且一致的密钥
+    Match,
+    // 该主机已登记密钥，但没有与之一致的（包括只登记了其他类型的密钥），可能是中间人攻击
+    Mismatch,
+    // 该主机没有登记任何密钥
+    Unknown,
+    // 密钥被 @revoked 标记吊销
+    Revoked,
+}
+
+/// 取出 SSH 地址中的端口，`ssh://host:2222/repo` 之外的地址都使用 22
+pub fn ssh_port(url: &str) -> u16 {
+    url.strip_prefix("ssh://")
+        .and_then(|rest| rest.split('/').next())
+        .map(|authority| authority.rsplit('@').next().unwrap_or(authority))
+        .and_then(|host| host.rsplit_once(':'))
+        .and_then(|(_, port)| port.parse().ok())
+        .unwrap_or(DEFAULT_SSH_PORT)
+}
+
+fn known_hosts_path() -> PathBuf {
+    let path = &app_config().ssh_config.known_hosts;
+    match (path.strip_prefix("~/"), std::env::var("HOME")) {
+        (Some(rest), Ok(home)) => PathBuf::from(home).join(rest),
+        _ => PathBuf::from(path),
+    }
+}
+
+/// known_hosts 中主机名的写法：22 端口直接写主机名，其余写成 `[host]:port`
+fn host_entry(host: &str, port: u16) -> String {
+    if port == DEFAULT_SSH_PORT {
+        host.to_string()
+    } else {
+        format!("[{}]:{}", host, port)
+    }
+}
+
+// 支持 * 和 ? 通配符
+pub fn glob_match(pattern: &str, name: &str) -> bool {
+    let (p, n): (Vec<char>, Vec<char>) = (pattern.chars().collect(), name.chars().collect());
+    let (mut pi, mut ni, mut star, mut mark) = (0, 0, None, 0);
+    while ni < n.len() {
+        if pi < p.len() && (p[pi] == '?' || p[pi] == n[ni]) {
+            pi += 1;
+            ni += 1;
+        } else if pi < p.len() && p[pi] == '*' {
+            star = Some(pi);
+            mark = ni;
+            pi += 1;
+        } else if let Some(s) = star {
+            pi = s + 1;
+            mark += 1;
+            ni = mark;
+        } else {
+            return false;
+        }
+    }
+    p[pi..].iter().all(|c| *c == '*')
+}
+
+// 哈希形式的主机名：|1|base64(salt)|base64(HMAC-SHA1(salt, host))
+fn hashed_match(pattern: &str, name: &str) -> bool {
+    let mut parts = pattern.trim_start_matches("|1|").split('|');
+    let (Some(salt), Some(hash)) = (parts.next(), parts.next()) else {
+        return false;
+    };
+    let (Ok(salt), Ok(hash)) = (BASE64_STANDARD.decode(salt), BASE64_STANDARD.decode(hash)) else {
+        return false;
+    };
+    let Ok(mut mac) = Hmac::<Sha1>::new_from_slice(&salt) else {
+        return false;
+    };
+    mac.update(name.as_bytes());
+    mac.verify_slice(&hash).is_ok()
+}
+
+/// 判断 known_hosts 一行的主机字段是否匹配，`!` 开头的否定模式优先
+fn hosts_match(field: &str, name: &str) -> bool {
+    if field.starts_with("|1|") {
+        return hashed_match(field, name);
+    }
+    let name = name.to_ascii_lowercase();
+    let mut matched = false;
+    for pattern in field.split(',') {
+        let pattern = pattern.to_ascii_lowercase();
+        if let Some(negated) = pattern.strip_prefix('!') {
+            if glob_match(negated, &name) {
+                return false;
+            }
+        } else if glob_match(&pattern, &name) {
+            matched = true;
+        }
+    }
+    matched
+}
+
+/// 在 known_hosts 内容中查找主机密钥
+pub fn lookup(content: &str, host: &str, port: u16, key_type: &str, key: &[u8]) -> HostKeyStatus {
+    let name = host_entry(host, port);
+    let mut status = HostKeyStatus::Unknown;
+    for line in content.lines() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let mut fields = line.split_whitespace();
+        let (marker, hosts) = match fields.next() {
+            Some(marker) if marker.starts_with('@') => (Some(marker), fields.next()),
+            hosts => (None, hosts),
+        };
+        let (Some(hosts), Some(entry_type), Some(entry_key)) =
+            (hosts, fields.next(), fields.next())
+        else {
+            continue;
+        };
+        if !hosts_match(hosts, &name) {
+            continue;
+        }
+        let same_key = BASE64_STANDARD
+            .decode(entry_key)
+            .is_ok_and(|entry_key| entry_key == key);
+        match marker {
+            Some("@revoked") if same_key => return HostKeyStatus::Revoked,
+            // 证书机构和未知标记不参与普通密钥比较
+            Some(_) => {}
+            None if entry_type == key_type && same_key => status = HostKeyStatus::Match,
+            None if status == HostKeyStatus::Unknown => status = HostKeyStatus::Mismatch,
+            None => {}
+        }
+    }
+    status
+}
+
+fn append_known_host(
+    path: &Path,
+    host: &str,
+    port: u16,
+    key_type: &str,
+    key: &[u8],
+) -> std::io::Result<()> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    let mut file = fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)?;
+    writeln!(
+        file,
+        "{} {} {}",
+        host_entry(host, port),
+        key_type,
+        BASE64_STANDARD.encode(key)
+    )?;
+    info!(
+        "已将 {} 的主机密钥写入 {}",
+        host_entry(host, port),
+        path.display()
+    );
+    Ok(())
+}
+
+/// 证书校验回调：SSH 连接按配置的策略校验主机密钥，HTTPS 交给 libgit2 默认校验
+pub fn check_host_key(
+    cert: &Cert<'_>,
+    host: &str,
+    port: u16,
+) -> Result<CertificateCheckStatus, git2::Error> {
+    let Some(hostkey) = cert.as_hostkey() else {
+        return Ok(CertificateCheckStatus::CertificatePassthrough);
+    };
+    let policy = app_config().ssh_config.host_key_policy;
+    if policy == HostKeyPolicy::AcceptAny {
+        warn!(
+            "⚠️ 未校验 {} 的主机密钥（host_key_policy = accept_any）",
+            host
+        );
+        return Ok(CertificateCheckStatus::CertificateOk);
+    }
+    let (Some(key), Some(key_type)) = (hostkey.hostkey(), hostkey.hostkey_type()) else {
+        return Err(git2::Error::from_str(&format!(
+            "无法获取 {} 的主机密钥",
+            host
+        )));
+    };
+    verify_known_host(
+        &known_hosts_path(),
+        policy,
+        host,
+        port,
+        key_type.name(),
+        key,
+    )
+    .map(|()| CertificateCheckStatus::CertificateOk)
+    .map_err(|e| git2::Error::from_str(&e))
+}
+
+/// 按策略用 known_hosts 文件校验主机密钥，accept_new 时把新主机写入文件
+fn verify_known_host(
+    path: &Path,
+    policy: HostKeyPolicy,
+    host: &str,
+    port: u16,
+    key_type: &str,
+    key: &[u8],
+) -> Result<(), String> {
+    let _guard = KNOWN_HOSTS_LOCK.lock().unwrap();
+    let content = fs::read_to_string(path).unwrap_or_default();
+    match lookup(&content, host, port, key_type, key) {
+        HostKeyStatus::Match => Ok(()),
+        HostKeyStatus::Unknown if policy == HostKeyPolicy::AcceptNew => {
+            append_known_host(path, host, port, key_type, key)
+                .map_err(|e| format!("写入 known_hosts 失败: {}", e))
+        }
+        HostKeyStatus::Unknown => Err(format!(
+            "{} 不在 known_hosts 中，拒绝连接",
+            host_entry(host, port)
+        )),
+        HostKeyStatus::Mismatch => {
+            warn!(
+                "⚠️ {} 的主机密钥与 known_hosts 不一致",
+                host_entry(host, port)
+            );
+            Err(format!(
+                "{} 的主机密钥与 known_hosts 不一致，可能存在中间人攻击",
+                host_entry(host, port)
+            ))
+        }
+        HostKeyStatus::Revoked => Err(format!("{} 的主机密钥已被吊销", host_entry(host, port))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // ssh-keygen 生成的测试密钥
+    const ED1: &str = "AAAAC3NzaC1lZDI1NTE5AAAAIO7z48vfF5OwhcZ+9YAMM7hBuaVR2ZjNHrUdwnefdXtm";
+    const ED2: &str = "AAAAC3NzaC1lZDI1NTE5AAAAID3GO5hhr7IlRzt9diOoZAzBtYwHIc8XLltlXt2aAz81";
+    const EC1: &str = "AAAAE2VjZHNhLXNoYTItbmlzdHAyNTYAAAAIbmlzdHAyNTYAAABBBKOPrcBFWsk1eKTnvOmIo1QdZV5GmPyGjyMMvTxLmdozxxmRSrwxn0OSBjKMZDTM9B2XC+s/zvYAwP+UdmE1lPQ=";
+    // `ssh-keygen -H` 对 git.example.com 和 [git.example.com]:2222 生成的哈希主机名
+    const HASHED_22: &str = "|1|RNN5YVfcLfBel0AvLhp6XDAdgdY=|Sq7yyhErRGZWAM70qgVU48o8hrQ=";
+    const HASHED_2222: &str = "|1|ZtSenq09yweaQ1gv3U4HuIUijFQ=|obUD1HtX/Ls05caKp6heYDDWvyM=";
+
+    fn key(encoded: &str) -> Vec<u8> {
+        BASE64_STANDARD.decode(encoded).unwrap()
+    }
+
+    fn ed(host: &str, port: u16, content: &str, encoded: &str) -> HostKeyStatus {
+        lookup(content, host, port, "ssh-ed25519", &key(encoded))
+    }
+
+    #[test]
+    fn parses_ssh_ports() {
+        assert_eq!(ssh_port("ssh://git@host:2222/a/b.git"), 2222);
+        assert_eq!(ssh_port("ssh://host/a/b.git"), 22);
+        assert_eq!(ssh_port("git@host:a/b.git"), 22);
+        assert_eq!(ssh_port("https://host:8443/a/b.git"), 22);
+        assert_eq!(ssh_port("ssh://git@host:x/a"), 22);
+    }
+
+    #[test]
+    fn matches_globs() {
+        assert!(glob_match("*.example.com", "git.example.com"));
+        assert!(glob_match("git?.example.com", "git1.example.com"));
+        assert!(glob_match("*", "anything"));
+        assert!(glob_match("a*b*c", "aXXbYYc"));
+        assert!(glob_match("[git.example.com]:*", "[git.example.com]:2222"));
+        assert!(!glob_match("*.example.com", "example.com"));
+        assert!(!glob_match("git?.example.com", "git.example.com"));
+        assert!(!glob_match("a*b", "aXbY"));
+        assert!(!glob_match("", "a"));
+        assert!(glob_match("", ""));
+    }
+
+    #[test]
+    fn matches_hashed_hosts() {
+        assert!(hashed_match(HASHED_22, "git.example.com"));
+        assert!(hashed_match(HASHED_2222, "[git.example.com]:2222"));
+        assert!(!hashed_match(HASHED_22, "[git.example.com]:2222"));
+        assert!(!hashed_match(HASHED_22, "other.example.com"));
+        assert!(!hashed_match("|1|not base64|x", "git.example.com"));
+        assert!(!hashed_match("|1|", "git.example.com"));
+    }
+
+    #[test]
+    fn looks_up_plain_entries() {
+        let content = format!(
+            "# 注释\n\ngit.example.com,10.0.0.1 ssh-ed25519 {ED1}\n[git.example.com]:2222 ssh-ed25519 {ED2}\n"
+        );
+        assert_eq!(
+            ed("git.example.com", 22, &content, ED1),
+            HostKeyStatus::Match
+        );
+        assert_eq!(
+            ed("GIT.example.com", 22, &content, ED1),
+            HostKeyStatus::Match
+        );
+        assert_eq!(ed("10.0.0.1", 22, &content, ED1), HostKeyStatus::Match);
+        assert_eq!(
+            ed("git.example.com", 2222, &content, ED2),
+            HostKeyStatus::Match
+        );
+        // 端口不同视为不同主机
+        assert_eq!(
+            ed("git.example.com", 22, &content, ED2),
+            HostKeyStatus::Mismatch
+        );
+        assert_eq!(
+            ed("git.example.com", 2200, &content, ED1),
+            HostKeyStatus::Unknown
+        );
+        assert_eq!(
+            ed("other.example.com", 22, &content, ED1),
+            HostKeyStatus::Unknown
+        );
+    }
+
+    #[test]
+    fn other_key_type_is_a_mismatch() {
+        let content = format!("git.example.com ecdsa-sha2-nistp256 {EC1}\n");
+        assert_eq!(
+            ed("git.example.com", 22, &content, ED1),
+            HostKeyStatus::Mismatch
+        );
+        // 类型对不上时，密钥内容相同也不算一致
+        let same_blob = format!("git.example.com ecdsa-sha2-nistp256 {ED1}\n");
+        assert_eq!(
+            ed("git.example.com", 22, &same_blob, ED1),
+            HostKeyStatus::Mismatch
+        );
+        // 同时登记了多种类型时，只要有一条一致即可
+        let both = format!("{content}git.example.com ssh-ed25519 {ED1}\n");
+        assert_eq!(ed("git.example.com", 22, &both, ED1), HostKeyStatus::Match);
+    }
+
+    #[test]
+    fn looks_up_hashed_wildcard_and_negated_entries() {
+        let hashed = format!("{HASHED_22} ssh-ed25519 {ED1}\n{HASHED_2222} ssh-ed25519 {ED2}\n");
+        assert_eq!(
+            ed("git.example.com", 22, &hashed, ED1),
+            HostKeyStatus::Match
+        );
+        assert_eq!(
+            ed("git.example.com", 2222, &hashed, ED2),
+            HostKeyStatus::Match
+        );
+        assert_eq!(
+            ed("git.example.com", 2222, &hashed, ED1),
+            HostKeyStatus::Mismatch
+        );
+
+        let wildcard = format!("*.example.com,!evil.example.com ssh-ed25519 {ED1}\n");
+        assert_eq!(
+            ed("git.example.com", 22, &wildcard, ED1),
+            HostKeyStatus::Match
+        );
+        assert_eq!(
+            ed("evil.example.com", 22, &wildcard, ED1),
+            HostKeyStatus::Unknown
+        );
+    }
+
+    #[test]
+    fn honours_markers() {
+        let content = format!(
+            "@revoked * ssh-ed25519 {ED2}\n@cert-authority *.example.com ssh-ed25519 {ED1}\n\
+             git.example.com ssh-ed25519 {ED2}\n"
+        );
+        assert_eq!(
+            ed("git.example.com", 22, &content, ED2),
+            HostKeyStatus::Revoked
+        );
+        // 证书机构的密钥不能直接当作主机密钥
+        assert_eq!(
+            ed("git.example.com", 22, &content, ED1),
+            HostKeyStatus::Mismatch
+        );
+        assert_eq!(
+            ed("other.example.com", 22, &content, ED1),
+            HostKeyStatus::Unknown
+        );
+    }
+
+    #[test]
+    fn skips_malformed_lines() {
+        let content = format!("git.example.com\ngit.example.com ssh-ed25519\n@revoked\n{ED1}\n");
+        assert_eq!(
+            ed("git.example.com", 22, &content, ED1),
+            HostKeyStatus::Unknown
+        );
+    }
+
+    #[test]
+    fn accept_new_records_unknown_hosts_only() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("ssh/known_hosts");
+        let verify = |policy, port, key_type, encoded: &str| {
+            verify_known_host(
+                &path,
+                policy,
+                "git.example.com",
+                port,
+                key_type,
+                &key(encoded),
+            )
+        };
+
+        assert!(verify(HostKeyPolicy::Strict, 22, "ssh-ed25519", ED1).is_err());
+        assert!(!path.exists());
+
+        // 首次连接写入 known_hosts，之后按 strict 校验
+        verify(HostKeyPolicy::AcceptNew, 22, "ssh-ed25519", ED1).unwrap();
+        verify(HostKeyPolicy::AcceptNew, 2222, "ssh-ed25519", ED2).unwrap();
+        assert_eq!(
+            fs::read_to_string(&path).unwrap(),
+            format!(
+                "git.example.com ssh-ed25519 {ED1}\n[git.example.com]:2222 ssh-ed25519 {ED2}\n"
+            )
+        );
+        verify(HostKeyPolicy::Strict, 22, "ssh-ed25519", ED1).unwrap();
+        verify(HostKeyPolicy::Strict, 2222, "ssh-ed25519", ED2).unwrap();
+
+        // 密钥变化或换了类型都拒绝，也不会追加
+        assert!(verify(HostKeyPolicy::AcceptNew, 22, "ssh-ed25519", ED2).is_err());
+        assert!(verify(HostKeyPolicy::AcceptNew, 22, "ecdsa-sha2-nistp256", EC1).is_err());
+        assert_eq!(fs::read_to_string(&path).unwrap().lines().count(), 2);
+    }
+
+    #[test]
+    fn strict_policy_rejects_changed_and_revoked_keys() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("known_hosts");
+        let content = format!("git.example.com ssh-ed25519 {ED1}\n@revoked * ssh-ed25519 {ED2}\n");
+        fs::write(&path, &content).unwrap();
+        let verify = |host, key_type, encoded: &str| {
+            verify_known_host(
+                &path,
+                HostKeyPolicy::Strict,
+                host,
+                22,
+                key_type,
+                &key(encoded),
+            )
+        };
+
+        verify("git.example.com", "ssh-ed25519", ED1).unwrap();
+        let err = verify("git.example.com", "ecdsa-sha2-nistp256", EC1).unwrap_err();
+        assert!(err.contains("不一致"), "{}", err);
+        let err = verify("git.example.com", "ssh-ed25519", ED2).unwrap_err();
+        assert!(err.contains("吊销"), "{}", err);
+        let err = verify("other.example.com", "ssh-ed25519", ED1).unwrap_err();
+        assert!(err.contains("不在 known_hosts 中"), "{}", err);
+        assert_eq!(fs::read_to_string(&path).unwrap(), content);
+    }
+}
@@ -0,0 +1,169 @@
+use crate::config::app_config::{GitConfigTable, app_config, flatten_git_config};
+use crate::repo::repo_path::{RepoPath, resolve_under};
+use anyhow::{Context, Result, anyhow};
+use chrono::Datelike;
+use git2::Repository;
+use log::info;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::{Path, PathBuf};
+use walkdir::WalkDir;
+
+/// 模板目录中的说明文件
+#[derive(Debug, Default, Deserialize)]
+struct TemplateManifest {
+    #[serde(default)]
+    description: String,
+    // 模板额外的 git 配置，会覆盖 [repo_config] 中的同名项
+    #[serde(default)]
+    config: GitConfigTable,
+}
+
+/// 服务端仓库模板
+#[derive(Debug, Serialize)]
+pub struct RepoTemplate {
+    pub name: String,
+    pub description: String,
+    pub files: Vec<String>,
+    pub hooks: Vec<String>,
+    pub config: Vec<(String, String)>,
+    #[serde(skip)]
+    dir: PathBuf,
+}
+
+fn template_root() -> PathBuf {
+    PathBuf::from(&app_config().template_config.dir)
+}
+
+/// 列出模板目录下的所有模板
+pub fn list_templates() -> Result<Vec<RepoTemplate>> {
+    let root = template_root();
+    if !root.exists() {
+        return Ok(Vec::new());
+    }
+    let mut templates = Vec::new();
+    for entry in fs::read_dir(&root).context("读取模板目录失败")? {
+        let entry = entry?;
+        if !entry.path().is_dir() {
+            continue;
+        }
+        if let Some(name) = entry.file_name().to_str() {
+            templates.push(load_template(name)?);
+        }
+    }
+    templates.sort_by(|a, b| a.name.cmp(&b.name));
+    Ok(templates)
+}
+
+/// 按名称加载模板，名称与仓库名使用同样的规则校验
+pub fn load_template(name: &str) -> Result<RepoTemplate> {
+    if name.contains('/') {
+        return Err(anyhow!("非法的模板名: {}", name));
+    }
+    RepoPath::parse(name).map_err(|e| anyhow!("非法的模板名 {}: {}", name, e))?;
+    let dir = resolve_under(&template_root(), Path::new(name))?;
+    if !dir.is_dir() {
+        return Err(anyhow!("模板不存在: {}", name));
+    }
+
+    let manifest_path = dir.join("template.toml");
+    let manifest: TemplateManifest = if manifest_path.exists() {
+        toml::from_str(&fs::read_to_string(&manifest_path)?)
+            .with_context(|| format!("解析模板说明失败: {}", manifest_path.display()))?
+    } else {
+        TemplateManifest::default()
+    };
+
+    Ok(RepoTemplate {
+        name: name.to_string(),
+        description: manifest.description,
+        files: list_files(&dir.join("files"))?,
+        hooks: list_files(&dir.join("hooks"))?,
+        config: flatten_git_config(&manifest.config),
+        dir,
+    })
+}
+
+fn list_files(dir: &Path) -> Result<Vec<String>> {
+    let mut files = Vec::new();
+    if !dir.exists() {
+        return Ok(files);
+    }
+    for entry in WalkDir::new(dir).sort_by_file_name() {
+        let entry = entry?;
+        if entry.file_type().is_file() {
+            let rel = entry.path().strip_prefix(dir)?;
+            files.push(rel.to_string_lossy().replace('\\', "/"));
+        }
+    }
+    Ok(files)
+}
+
+impl RepoTemplate {
+    /// 把 files/ 下的文件写入工作目录，文本文件中的占位符会被替换
+    pub fn write_files(&self, work_dir: &Path, repo_name: &RepoPath) -> Result<()> {
+        let year = chrono::Local::now().year().to_string();
+        for file in &self.files {
+            let src = self.dir.join("files").join(file);
+            let dest = resolve_under(work_dir, Path::new(file))?;
+            if let Some(parent) = dest.parent() {
+                fs::create_dir_all(parent)?;
+            }
+            let content = fs::read(&src)?;
+            match String::from_utf8(content) {
+                Ok(text) => fs::write(
+                    &dest,
+                    text.replace("{{repo_name}}", repo_name.name())
+                        .replace("{{year}}", &year),
+                )?,
+                Err(e) => fs::write(&dest, e.into_bytes())?,
+            }
+            info!("写入模板文件: {}", file);
+        }
+        Ok(())
+    }
+
+    /// 把 hooks/ 下的钩子安装到仓库的 hooks 目录并设置可执行权限
+    pub fn install_hooks(&self, repo: &Repository) -> Result<()> {
+        let hooks_dir = repo.path().join("hooks");
+        fs::create_dir_all(&hooks_dir)?;
+        for hook in &self.hooks {
+            let dest = hooks_dir.join(hook);
+            if let Some(parent) = dest.parent() {
+                fs::create_dir_all(parent)?;
+            }
+            fs::copy(self.dir.join("hooks").join(hook), &dest)
+                .with_context(|| format!("安装钩子失败: {}", hook))?;
+            #[cfg(unix)]
+            {
+                use std::os::unix::fs::PermissionsExt;
+                fs::set_permissions(&dest, fs::Permissions::from_mode(0o755))?;
+            }
+            info!("安装钩子: {}", hook);
+        }
+        Ok(())
+    }
+}
+
+/// 新仓库使用的 git 配置：[repo_config] 加上模板配置，后者优先
+pub fn repo_config_entries(template: Option<&RepoTemplate>) -> Vec<(String, String)> {
+    let mut entries = flatten_git_config(&app_config().repo_config);
+    if let Some(template) = template {
+        for (key, value) in &template.config {
+            entries.retain(|(k, _)| k != key);
+            entries.push((key.clone(), value.clone()));
+        }
+    }
+    entries
+}
+
+/// 把配置项写入仓库的 config
+pub fn apply_config(repo: &Repository, entries: &[(String, String)]) -> Result<()> {
+    let mut config = repo.config()?;
+    for (key, value) in entries {
+        config
+            .set_str(key, value)
+            .with_context(|| format!("写入配置 {} 失败", key))?;
+    }
+    Ok(())
+}